@@ -0,0 +1,293 @@
+use crate::{
+    config::Config,
+    error::Result,
+    events::http::Request,
+    fsext::FilePattern,
+    inbox::{ FragmentStatus, Inbox },
+    log::{ self, Value },
+    metrics::Reload
+};
+
+
+/// The paths served by the admin API
+const PATHS: &[&str] = &["/fragments", "/config", "/error", "/reload", "/pause", "/resume"];
+
+
+/// The admin API state
+#[derive(Debug, Default)]
+pub struct Admin {
+    /// Whether watching the inbox is paused
+    paused: bool,
+    /// Whether the inbox has changed while watching was paused
+    pending: bool,
+    /// The time and message of the last error (if any)
+    last_error: Option<(String, String)>
+}
+impl Admin {
+    /// Creates a new admin API state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether watching the inbox is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Defers a directory change until watching is resumed
+    pub fn defer(&mut self) {
+        log::debug("reload", "Directory changed while paused; deferring the reload").emit();
+        self.pending = true;
+    }
+
+    /// Records `message` as the last error
+    pub fn record_error(&mut self, message: String) {
+        self.last_error = Some((log::now(), message));
+    }
+
+    /// Records the `outcome` of a reload together with its `message`
+    pub fn reloaded(&mut self, outcome: Reload, message: &str) {
+        self.pending = false;
+        if matches!(outcome, Reload::Refused | Reload::Failure) {
+            self.record_error(message.to_string());
+        }
+    }
+
+    /// Serves a `request` to the admin API; `reload` reloads the config and returns the outcome with a description
+    ///
+    /// # Note
+    /// This only fails if `reload` fails, i.e. if HAProxy could not be restarted.
+    pub fn serve<P, F>(
+        &mut self, request: Request, inbox: &Inbox<P>, config: &Config<P>, shutdown: bool, reload: F
+    ) -> Result where P: FilePattern, F: FnOnce() -> Result<(Reload, String)> {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/fragments") => self.fragments(request, inbox, config.generation()),
+            ("GET", "/config") => request.respond(200, "text/plain", &config.written()),
+            ("GET", "/error") => self.error(request),
+            ("POST", "/reload" | "/resume") if shutdown => request.respond(409, "text/plain", b"Shutting down\n"),
+            ("POST", "/pause") => self.pause(request),
+            ("POST", "/resume") if !self.pending => self.resume(request),
+            ("POST", "/reload" | "/resume") => return self.reload(request, reload),
+            (_, path) if PATHS.contains(&path) => request.respond(405, "text/plain", b"Method not allowed\n"),
+            _ => request.respond(404, "text/plain", b"Not found\n")
+        }
+        Ok(())
+    }
+
+    /// Serves the state of each fragment as of the last scan
+    fn fragments<P>(&self, request: Request, inbox: &Inbox<P>, generation: u64) where P: FilePattern {
+        // Serialize the fragment states without rescanning, since a scan updates the stability state and the metrics
+        let fragments = inbox.states().into_iter().map(|state| {
+            let (status, reason) = match state.status {
+                FragmentStatus::Accepted => ("accepted", None),
+                FragmentStatus::Deferred => ("deferred", None),
+                FragmentStatus::Rejected(reason) => ("rejected", Some(reason))
+            };
+            let mut fields =
+                vec![("path", Value::String(state.path.display().to_string())), ("status", Value::from(status))];
+            fields.extend(state.digest.map(|digest| ("digest", Value::String(digest))));
+            fields.extend(reason.map(|reason| ("reason", Value::String(reason))));
+            Value::Object(fields)
+        });
+        let fields = [
+            ("generation", Value::from(generation)), ("paused", Value::from(self.paused)),
+            ("fragments", Value::Array(fragments.collect()))
+        ];
+        let body = log::json(fields.iter().map(|(name, value)| (*name, value)));
+        request.respond(200, "application/json", body.as_bytes());
+    }
+
+    /// Serves the last error (if any)
+    fn error(&self, request: Request) {
+        let fields = match &self.last_error {
+            Some((time, message)) => vec![("time", Value::from(time.as_str())), ("message", Value::from(message.as_str()))],
+            None => Vec::new()
+        };
+        let body = log::json(fields.iter().map(|(name, value)| (*name, value)));
+        request.respond(200, "application/json", body.as_bytes());
+    }
+
+    /// Pauses watching the inbox
+    fn pause(&mut self, request: Request) {
+        if !self.paused {
+            log::info("paused", "Paused watching the inbox").emit();
+        }
+        self.paused = true;
+        request.respond(200, "text/plain", b"Paused watching the inbox\n");
+    }
+
+    /// Resumes watching the inbox if it has not changed while watching was paused
+    fn resume(&mut self, request: Request) {
+        if self.paused {
+            log::info("resumed", "Resumed watching the inbox").emit();
+        }
+        self.paused = false;
+        request.respond(200, "text/plain", b"Resumed watching the inbox\n");
+    }
+
+    /// Reloads on request or applies the changes that have been made while watching was paused
+    fn reload<F>(&mut self, request: Request, reload: F) -> Result where F: FnOnce() -> Result<(Reload, String)> {
+        // Log the cause
+        let record = match request.path.as_str() {
+            "/resume" => {
+                self.paused = false;
+                log::info("resumed", "Resumed watching the inbox").emit();
+                log::info("reload", "Directory changed while paused; reloading...").field("cause", "resume")
+            },
+            _ => log::info("reload", "Got admin request; reloading...").field("cause", "admin")
+        };
+        record.emit();
+
+        // Reload and report the outcome
+        let (outcome, message) = match reload() {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                request.respond(500, "text/plain", format!("Failed to reload: {}\n", e).as_bytes());
+                return Err(e);
+            }
+        };
+        self.reloaded(outcome, &message);
+        let status = match outcome {
+            Reload::Success => 200,
+            Reload::Refused | Reload::Deferred => 409,
+            Reload::Failure => 500
+        };
+        request.respond(status, "text/plain", format!("{}\n", message).as_bytes());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config,
+        error::Error,
+        events::{ http::HttpEventSource, EventSource },
+        fsext::{ self, FileExtensionPattern }
+    };
+    use std::{
+        fs,
+        io::{ Read, Write },
+        os::unix::net::UnixStream,
+        path::PathBuf
+    };
+
+    /// The fragment within the test inbox
+    const FRAGMENT: &str = "backend a\n";
+
+    /// An admin API with an assembled config and a listener for test requests
+    struct Fixture {
+        /// The admin API state
+        admin: Admin,
+        /// The inbox
+        inbox: Inbox<FileExtensionPattern>,
+        /// The config assembled from the inbox
+        config: Config<FileExtensionPattern>,
+        /// The HTTP listener
+        source: HttpEventSource,
+        /// The path to the HTTP socket
+        socket: PathBuf
+    }
+    impl Fixture {
+        /// Creates a fixture within the test directory `name`
+        fn new(name: &str) -> Self {
+            let directory = fsext::test_dir(name);
+            let inbox_dir = directory.join("inbox");
+            fs::create_dir(&inbox_dir).expect("failed to create inbox");
+            fs::write(inbox_dir.join("000-a.cfg"), FRAGMENT).expect("failed to write fragment");
+
+            let inbox = Inbox::new(&inbox_dir, FileExtensionPattern::new(".cfg"));
+            let config = Config::new(inbox.clone(), directory.join("haproxy.cfg"));
+            config.assemble().expect("failed to assemble config");
+            let socket = directory.join("admin.sock");
+            let source = HttpEventSource::bind(Some(&socket), None).expect("failed to bind admin socket");
+            Self { admin: Admin::new(), inbox, config, source, socket }
+        }
+
+        /// Sends `method` and `path` to the admin API and returns the response and the result of serving the request
+        fn request<F>(&mut self, method: &str, path: &str, shutdown: bool, reload: F) -> (String, Result)
+            where F: FnOnce() -> Result<(Reload, String)>
+        {
+            let mut client = UnixStream::connect(&self.socket).expect("failed to connect to admin socket");
+            client.write_all(format!("{} {} HTTP/1.1\r\n\r\n", method, path).as_bytes()).expect("failed to send request");
+            let request = loop {
+                if let Some(request) = self.source.event_poll().expect("failed to poll admin socket").pop() {
+                    break request;
+                }
+            };
+
+            let result = self.admin.serve(request, &self.inbox, &self.config, shutdown, reload);
+            let mut response = String::new();
+            client.read_to_string(&mut response).expect("failed to read response");
+            (response, result)
+        }
+    }
+
+    /// Fails the test if a reload is triggered
+    fn no_reload() -> Result<(Reload, String)> {
+        panic!("unexpected reload")
+    }
+
+    /// Gets the status code and the body of `response`
+    fn split(response: &str) -> (&str, &str) {
+        let status = response.split(' ').nth(1).expect("missing status code");
+        let (_, body) = response.split_once("\r\n\r\n").expect("missing body");
+        (status, body)
+    }
+
+    #[test]
+    fn routes() {
+        let mut fixture = Fixture::new("admin-routes");
+        let (response, _) = fixture.request("GET", "/config", false, no_reload);
+        assert_eq!(split(&response), ("200", FRAGMENT));
+        assert_eq!(split(&fixture.request("GET", "/reload", false, no_reload).0).0, "405");
+        assert_eq!(split(&fixture.request("GET", "/missing", false, no_reload).0).0, "404");
+        assert_eq!(split(&fixture.request("GET", "/error", false, no_reload).0), ("200", "{}"));
+
+        // The fragments are served as of the last scan
+        let path = fixture.inbox.directory().join("000-a.cfg");
+        let (response, _) = fixture.request("GET", "/fragments", false, no_reload);
+        let expected = format!(
+            r#"{{"generation":1,"paused":false,"fragments":[{{"path":"{}","status":"accepted","digest":"{}"}}]}}"#,
+            path.display(), config::digest(FRAGMENT.as_bytes())
+        );
+        assert_eq!(split(&response), ("200", expected.as_str()));
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut fixture = Fixture::new("admin-pause");
+        assert_eq!(split(&fixture.request("POST", "/pause", false, no_reload).0).0, "200");
+        assert!(fixture.admin.is_paused());
+        assert_eq!(split(&fixture.request("POST", "/resume", false, no_reload).0).0, "200");
+        assert!(!fixture.admin.is_paused());
+
+        // Resuming applies the deferred changes
+        assert_eq!(split(&fixture.request("POST", "/pause", false, no_reload).0).0, "200");
+        fixture.admin.defer();
+        let (response, _) = fixture.request("POST", "/resume", false, || Ok((Reload::Success, "Reloaded".to_string())));
+        assert_eq!(split(&response), ("200", "Reloaded\n"));
+        assert!(!fixture.admin.is_paused());
+        assert_eq!(split(&fixture.request("POST", "/resume", false, no_reload).0).0, "200");
+    }
+
+    #[test]
+    fn reload_outcomes() {
+        let mut fixture = Fixture::new("admin-reload");
+        let (response, _) = fixture.request("POST", "/reload", false, || Ok((Reload::Refused, "Refused".to_string())));
+        assert_eq!(split(&response), ("409", "Refused\n"));
+        let (response, _) = fixture.request("GET", "/error", false, no_reload);
+        assert!(split(&response).1.ends_with(r#","message":"Refused"}"#));
+        let (response, _) = fixture.request("POST", "/reload", false, || Ok((Reload::Failure, "Failed".to_string())));
+        assert_eq!(split(&response), ("500", "Failed\n"));
+        let (response, _) = fixture.request("POST", "/reload", true, no_reload);
+        assert_eq!(split(&response), ("409", "Shutting down\n"));
+
+        // A failed restart is reported to the client and to the caller
+        let (response, result) = fixture.request("POST", "/reload", false, || Err(Error::Poisoned("child process")));
+        assert_eq!(split(&response), ("500", "Failed to reload: child process is poisoned\n"));
+        assert!(matches!(result, Err(Error::Poisoned(_))));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::Metadata,
+    os::unix::fs::MetadataExt,
+    str::FromStr
+};
+
+
+/// A comma-separated list of numeric user or group IDs
+///
+/// # Note
+/// An empty list is rejected, since it would reject every fragment instead of allowing every owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdList(pub Vec<u32>);
+impl FromStr for IdList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ids = s.split(',').map(str::trim).filter(|id| !id.is_empty())
+            .map(|id| id.parse().map_err(|_| format!("Invalid ID: {}", id)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if ids.is_empty() {
+            return Err(format!("Invalid ID list: {:?} (expected at least one ID)", s));
+        }
+        Ok(Self(ids))
+    }
+}
+
+
+/// The owner of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    /// A user ID
+    Uid(u32),
+    /// A group ID
+    Gid(u32)
+}
+
+
+/// A comma-separated mapping from owners to tenant names (e.g. `uid:1000=alice,gid:2000=bob`)
+#[derive(Debug, Clone, Default)]
+pub struct TenantMap(pub Vec<(Owner, String)>);
+impl FromStr for TenantMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            // Split the entry
+            let invalid = || format!("Invalid tenant mapping: {} (expected uid:ID=NAME or gid:ID=NAME)", entry);
+            let (owner, name) = entry.split_once('=').ok_or_else(invalid)?;
+            let (kind, id) = owner.split_once(':').ok_or_else(invalid)?;
+            let id = id.parse().map_err(|_| invalid())?;
+
+            // Parse the owner
+            let owner = match kind {
+                "uid" => Owner::Uid(id),
+                "gid" => Owner::Gid(id),
+                _ => return Err(invalid())
+            };
+            entries.push((owner, name.to_string()));
+        }
+        Ok(Self(entries))
+    }
+}
+
+
+/// Admission checks for fragments based on their ownership and permissions
+#[derive(Debug, Clone, Default)]
+pub struct Admission {
+    /// Whether world-writable fragments are allowed
+    allow_world_writable: bool,
+    /// The UIDs that may own fragments (or `None` if all UIDs are allowed)
+    allowed_uids: Option<IdList>,
+    /// The tenant mapping
+    tenants: TenantMap
+}
+impl Admission {
+    /// Creates new admission checks that reject world-writable fragments if `allow_world_writable` is not set
+    pub fn new(allow_world_writable: bool) -> Self {
+        Self { allow_world_writable, ..Default::default() }
+    }
+    /// Only allows fragments owned by the given UIDs
+    pub fn with_allowed_uids(mut self, uids: Option<IdList>) -> Self {
+        self.allowed_uids = uids;
+        self
+    }
+    /// Sets the tenant mapping
+    pub fn with_tenants(mut self, tenants: TenantMap) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
+    /// Checks whether a fragment with the given metadata is admitted and returns the associated tenant (if any) or the
+    /// reason for the rejection
+    pub fn check(&self, metadata: &Metadata) -> Result<Option<String>, String> {
+        // Check the permissions
+        const WORLD_WRITABLE: u32 = 0o002;
+        if !self.allow_world_writable && metadata.mode() & WORLD_WRITABLE != 0 {
+            return Err(format!("fragment is world-writable (mode {:04o})", metadata.mode() & 0o7777));
+        }
+
+        // Check the owner
+        if let Some(IdList(uids)) = &self.allowed_uids {
+            if !uids.contains(&metadata.uid()) {
+                return Err(format!("fragment is owned by UID {} which is not allowed", metadata.uid()));
+            }
+        }
+
+        // Resolve the tenant; UID mappings take precedence over GID mappings
+        let TenantMap(tenants) = &self.tenants;
+        let by_owner = |owner: Owner| tenants.iter().find(|(o, _)| *o == owner).map(|(_, name)| name.clone());
+        Ok(by_owner(Owner::Uid(metadata.uid())).or_else(|| by_owner(Owner::Gid(metadata.gid()))))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext;
+    use std::{ fs, os::unix::fs::PermissionsExt };
+
+    /// Creates a file with the permissions `mode` and returns its metadata
+    fn metadata(name: &str, mode: u32) -> Metadata {
+        let path = fsext::test_dir(name).join("fragment.cfg");
+        fs::write(&path, "").expect("failed to write file");
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).expect("failed to set permissions");
+        fs::metadata(&path).expect("failed to read metadata")
+    }
+
+    #[test]
+    fn parse_id_lists() {
+        assert_eq!("1000".parse(), Ok(IdList(vec![1000])));
+        assert_eq!(" 0, 1000 ,,1001".parse(), Ok(IdList(vec![0, 1000, 1001])));
+        assert!("1000,alice".parse::<IdList>().is_err());
+        assert!("-1".parse::<IdList>().is_err());
+
+        // An empty list is rejected instead of rejecting every fragment
+        assert!("".parse::<IdList>().is_err());
+        assert!(" , ".parse::<IdList>().is_err());
+    }
+
+    #[test]
+    fn parse_tenant_maps() {
+        let TenantMap(tenants) = "uid:1000=alice, gid:2000=bob,".parse().expect("invalid tenant map");
+        assert_eq!(tenants, [(Owner::Uid(1000), "alice".to_string()), (Owner::Gid(2000), "bob".to_string())]);
+        assert!("".parse::<TenantMap>().is_ok_and(|TenantMap(tenants)| tenants.is_empty()));
+        assert!("user:1000=alice".parse::<TenantMap>().is_err());
+        assert!("uid:1000".parse::<TenantMap>().is_err());
+        assert!("uid=alice".parse::<TenantMap>().is_err());
+        assert!("uid:x=alice".parse::<TenantMap>().is_err());
+    }
+
+    #[test]
+    fn world_writable_fragments() {
+        let metadata = metadata("admission-world-writable", 0o666);
+        let error = Admission::new(false).check(&metadata).expect_err("world-writable fragment was admitted");
+        assert_eq!(error, "fragment is world-writable (mode 0666)");
+        assert_eq!(Admission::new(true).check(&metadata), Ok(None));
+    }
+
+    #[test]
+    fn allowed_owners() {
+        let metadata = metadata("admission-owners", 0o644);
+        let (uid, other) = (metadata.uid(), metadata.uid().wrapping_add(1));
+        let admission = Admission::new(false).with_allowed_uids(Some(IdList(vec![other, uid])));
+        assert_eq!(admission.check(&metadata), Ok(None));
+        let admission = Admission::new(false).with_allowed_uids(Some(IdList(vec![other])));
+        let error = admission.check(&metadata).expect_err("foreign fragment was admitted");
+        assert_eq!(error, format!("fragment is owned by UID {} which is not allowed", uid));
+    }
+
+    #[test]
+    fn tenants_by_owner() {
+        let metadata = metadata("admission-tenants", 0o644);
+        let (uid, gid) = (Owner::Uid(metadata.uid()), Owner::Gid(metadata.gid()));
+
+        // UID mappings take precedence over GID mappings
+        let admission = Admission::new(false).with_tenants(TenantMap(vec![(gid, "group".to_string())]));
+        assert_eq!(admission.check(&metadata), Ok(Some("group".to_string())));
+        let tenants = TenantMap(vec![(gid, "group".to_string()), (uid, "user".to_string())]);
+        assert_eq!(Admission::new(false).with_tenants(tenants).check(&metadata), Ok(Some("user".to_string())));
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+
+/// An exponential backoff
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// The initial delay
+    initial: Duration,
+    /// The maximum delay
+    max: Duration,
+    /// The next delay (or `None` if the backoff is not active)
+    next: Option<Duration>
+}
+impl Backoff {
+    /// Creates a new exponential backoff
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, next: None }
+    }
+
+    /// Whether the backoff is active (i.e. [`Self::next`] has been called since the last reset)
+    pub fn is_active(&self) -> bool {
+        self.next.is_some()
+    }
+    /// Gets the next delay and doubles the delay up to the maximum
+    pub fn next(&mut self) -> Duration {
+        let delay = self.next.unwrap_or(self.initial);
+        self.next = Some(delay.saturating_mul(2).min(self.max));
+        delay
+    }
+    /// Resets the backoff to the initial delay
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        assert!(!backoff.is_active());
+        let delays: Vec<_> = (0..5).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        assert!(backoff.is_active());
+
+        // A reset starts over
+        backoff.reset();
+        assert!(!backoff.is_active());
+        assert_eq!(backoff.next(), Duration::from_secs(1));
+    }
+}
@@ -1,7 +1,15 @@
-use crate::events::child::ChildEventSource;
+use crate::{
+    error::{ Context, Error, Result },
+    events::{
+        child::{ ChildEventSource, WatchedChild },
+        output::OutputEventSource
+    },
+    init
+};
 use std::{
-    process::{ Command, Child },
-    sync::{ Arc, Mutex }
+    process::{ Command, Stdio },
+    sync::{ Arc, Mutex },
+    time::Duration
 };
 
 
@@ -11,40 +19,116 @@ pub struct ChildProcess {
     binary: String,
     /// The arguments to pass during execution
     args: Vec<String>,
+    /// The additional environment variables to pass during execution
+    env: Vec<(String, String)>,
     /// The child process
-    child: Arc<Mutex<Child>>
+    child: Arc<Mutex<WatchedChild>>,
+    /// The captured stdout and stderr of all child processes
+    output: OutputEventSource
 }
 impl ChildProcess {
-    /// Spawns a new process
-    pub fn new<B, A, AT>(binary: B, args: A) -> Self where B: ToString, A: IntoIterator<Item = AT>, AT: ToString {
+    /// Spawns a new process with the additional environment variables `env` that runs the config `generation`
+    pub fn new<B, A, AT>(binary: B, args: A, env: Vec<(String, String)>, generation: u64) -> Result<Self>
+        where B: ToString, A: IntoIterator<Item = AT>, AT: ToString
+    {
         // Collect the process info
         let binary = binary.to_string();
         let args: Vec<_> = args.into_iter().map(|a| a.to_string()).collect();
         
         // Spawn the child
-        let child = Command::new(&binary).args(&args).spawn().expect("Failed to spawn process");
-        Self { binary, args, child: Arc::new(Mutex::new(child)) }
+        let output = OutputEventSource::new();
+        let child = Self::spawn(&binary, &args, &env, &output, generation)?;
+        Ok(Self { binary, args, env, child: Arc::new(Mutex::new(child)), output })
     }
 
-    /// Restarts the child process
-    pub fn restart(&self) {
-        let mut child = self.child.lock().expect("Failed to lock mutex to access child process");
-        let _ = child.kill();
-        *child = Command::new(&self.binary).args(&self.args).spawn().expect("Failed to spawn process");
+    /// Restarts the child process to run the config `generation`
+    pub fn restart(&self, generation: u64) -> Result {
+        let mut child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        let _ = child.child.kill();
+        let _ = child.child.wait();
+        *child = Self::spawn(&self.binary, &self.args, &self.env, &self.output, generation)?;
+        Ok(())
+    }
+    /// Sends `signal` to the current child process; returns `false` if the child process has already exited
+    pub fn signal(&self, signal: libc::c_int) -> Result<bool> {
+        let child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        child.signal(signal).operation("signal child process")
+    }
+    /// Reaps all exited children including orphaned descendants; the exit of the current child process is still
+    /// reported by its event source
+    pub fn reap(&self) -> Result {
+        // Reap the current child first, so that its PID is not mistaken for it if the PID gets reused
+        let mut child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        let exited = child.child.try_wait().operation("query child process state")?.is_some();
+        let own = (!exited).then_some(child.child.id());
+        init::reap(own, || child.child.try_wait().map(|_| ()))
+    }
+    /// The PID of the current child process
+    pub fn pid(&self) -> Result<u32> {
+        let child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        Ok(child.child.id())
+    }
+    /// The time the current child process has been running
+    pub fn uptime(&self) -> Result<Duration> {
+        let child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        Ok(child.uptime())
     }
-    /// Creates a new child process event source for crash events
+    /// Creates a new child process event source for exit events
     pub fn event_source(&self) -> ChildEventSource {
         ChildEventSource::new(self.child.clone())
     }
+    /// Creates a new output event source for the lines written to stdout and stderr
+    pub fn output_source(&self) -> OutputEventSource {
+        self.output.clone()
+    }
     /// Sends a SIGKILL to the child
     pub fn kill(self) {
         drop(self);
     }
+
+    /// Spawns `binary` with its stdout and stderr captured by `output`
+    fn spawn(binary: &str, args: &[String], env: &[(String, String)], output: &OutputEventSource, generation: u64)
+        -> Result<WatchedChild>
+    {
+        let mut command = Command::new(binary);
+        command.args(args).envs(env.iter().map(|(name, value)| (name, value)));
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = command.spawn().context("spawn", binary)?;
+        if let Err(e) = output.capture(&mut child, generation) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        WatchedChild::new(child, generation)
+    }
 }
 impl Drop for ChildProcess {
     fn drop(&mut self) {
         if let Ok(mut child) = self.child.lock() {
-            let _ = child.kill();
+            let _ = child.child.kill();
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ reactor::Reactor, timer::TimerEventSource };
+    use std::time::Instant;
+
+    #[test]
+    fn soft_stop_before_the_drain_timeout() {
+        let haproxy = ChildProcess::new("sleep", ["10"], Vec::new(), 1).expect("failed to spawn child");
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        let drain = TimerEventSource::new();
+        reactor.attach(haproxy.event_source(), |exit| Some(exit.code()), None);
+        reactor.attach(drain.clone(), |_| None, None);
+
+        // The child exits on the soft-stop signal before the drain timer fires
+        assert!(haproxy.signal(libc::SIGTERM).expect("failed to signal child"));
+        drain.arm(Instant::now() + Duration::from_secs(5));
+        assert_eq!(reactor.wait().expect("failed to wait"), [Some(128 + libc::SIGTERM)]);
+        assert!(!haproxy.signal(libc::SIGKILL).expect("failed to signal child"));
+    }
+}
@@ -1,49 +1,178 @@
-use crate::fsext::{ self, FilePattern };
-use std::{ fs, path::PathBuf };
+use crate::{
+    error::{ Context, Error, Result },
+    fsext::{ self, FilePattern },
+    guard::{ DeletionGuard, Reference, CONFIRM_MARKER },
+    inbox::Inbox,
+    log,
+    metrics::Metrics,
+    transaction::{ self, TransactionMode }
+};
+use sha2::{ Digest, Sha256 };
+use std::{
+    cell::{ Cell, RefCell },
+    collections::BTreeMap,
+    fmt::Write,
+    path::PathBuf,
+    time::Instant
+};
+
+
+/// Computes the hex encoded SHA-256 digest of `data`
+pub fn digest(data: &[u8]) -> String {
+    let mut hex = String::new();
+    for byte in Sha256::digest(data) {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+
+/// A summary of an assembled config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// The fragments that have been added, modified or removed since the last assembly
+    pub changed: Vec<PathBuf>,
+    /// The amount of fragments
+    pub fragments: usize,
+    /// The size of the config in bytes
+    pub size: usize,
+    /// The digest of the config (see [`digest`])
+    pub digest: String
+}
 
 
 /// A config file manager
 pub struct Config<P> {
-    /// The directory containing the config file fragments
-    directory: PathBuf,
+    /// The inbox containing the config file fragments
+    inbox: Inbox<P>,
     /// The path to the final config file
     file: PathBuf,
-    /// The file extension pattern for config files
-    pattern: P
+    /// The guard against mass-deletions
+    guard: DeletionGuard,
+    /// The reference amount of fragments for the deletion guard
+    reference: RefCell<Reference>,
+    /// The digests of the fragments within the last assembled config
+    digests: RefCell<BTreeMap<PathBuf, String>>,
+    /// The generation of the last written config (incremented on every write)
+    generation: Cell<u64>,
+    /// The last written config
+    written: RefCell<Vec<u8>>,
+    /// The last-known-good config (i.e. the last config HAProxy has been running stable with)
+    known_good: RefCell<Option<Vec<u8>>>,
+    /// The metrics for assemblies
+    metrics: Metrics
 }
 impl<P> Config<P> {
     /// Creates a new config file manager
-    pub fn new<D, F>(directory: D, file: F, pattern: P) -> Self
-        where D: Into<PathBuf>, F: Into<PathBuf>
-    {
-        Self { directory: directory.into(), file: file.into(), pattern }
-    }
-
-    /// Assembles the config
-    pub fn assemble(&self) where P: FilePattern {
-        // List and sort the entries
-        let mut files: Vec<_> = fsext::list_files(&self.directory).expect("Failed to list directory")
-            .into_iter().map(|p| p.canonicalize().expect("Failed to canonicalize path"))
-            .collect();
-        files.sort();
-
-        // Read all config files
-        let mut config = Vec::new();
-        'read_loop: for path in files {
-            // Check if the path matches the pattern
-            let name = path.components().last().expect("Path has no last component");
-            let name_bytes = fsext::path_bytes(&name);
-            if !self.pattern.matches(&name_bytes) {
-                continue 'read_loop;
+    pub fn new<F>(inbox: Inbox<P>, file: F) -> Self where F: Into<PathBuf> {
+        Self {
+            inbox, file: file.into(), guard: DeletionGuard::default(), reference: Default::default(),
+            digests: RefCell::new(BTreeMap::new()), generation: Cell::new(0), written: RefCell::new(Vec::new()),
+            known_good: RefCell::new(None), metrics: Metrics::new()
+        }
+    }
+    /// Sets the guard against mass-deletions
+    pub fn with_guard(mut self, guard: DeletionGuard) -> Self {
+        self.guard = guard;
+        self
+    }
+    /// Records the assembly durations, fragment counts and config sizes to `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The generation of the last written config (`0` if no config has been written yet)
+    pub fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+    /// The last written config
+    pub fn written(&self) -> Vec<u8> {
+        self.written.borrow().clone()
+    }
+
+    /// Marks the last written config as last-known-good
+    pub fn mark_good(&self) {
+        let written = self.written.borrow().clone();
+        *self.known_good.borrow_mut() = Some(written);
+    }
+    /// Whether a last-known-good config is available that differs from the last written config
+    pub fn can_fallback(&self) -> bool {
+        self.known_good.borrow().as_ref().is_some_and(|known_good| *known_good != *self.written.borrow())
+    }
+    /// Restores the last-known-good config
+    pub fn fallback(&self) -> Result {
+        let known_good = self.known_good.borrow().clone();
+        if let Some(known_good) = known_good {
+            self.write(known_good)?;
+        }
+        Ok(())
+    }
+
+    /// Assembles and writes the config
+    ///
+    /// # Note
+    /// If the fragments are refused, [`Error::Refused`] is returned and the current config is kept. If the inbox is in the
+    /// middle of a transaction (see [`TransactionMode`]), [`Error::Pending`] is returned and the current config is kept.
+    pub fn assemble(&self) -> Result<Assembly> where P: FilePattern {
+        // Read all config files once the transaction is complete
+        let started = Instant::now();
+        let transactions = self.inbox.transactions();
+        let commit = transactions.check(self.inbox.directory()).map_err(Error::Pending)?;
+        let fragments = self.inbox.fragments()?;
+        if transactions != TransactionMode::Off && self.inbox.is_settling() {
+            return Err(Error::Pending("some fragments are still being written".to_string()));
+        }
+
+        // Check for mass-deletions unless the change has been confirmed
+        let confirmed = TransactionMode::marker(self.inbox.directory(), CONFIRM_MARKER).is_some();
+        if let Err(e) = self.guard.check(self.reference.borrow().get(started), fragments.len()) {
+            if !confirmed {
+                let reason = format!("!!! {}; create {} within the inbox to apply the change anyway !!!", e, CONFIRM_MARKER);
+                return Err(Error::Refused(reason));
             }
+            log::warn("confirmed_change", format!("Applying confirmed change: {}", e)).error(e).emit();
+        }
+        let count = fragments.len();
 
-            // Read the file
-            let data = fs::read(&path).expect("Failed to read file");
-            config.extend(data);
+        // Concatenate the fragments and detect the changed ones
+        let (mut config, mut digests) = (Vec::new(), BTreeMap::new());
+        for fragment in fragments {
+            digests.insert(fragment.path, digest(&fragment.data));
+            config.extend(fragment.data);
+
+            // Terminate the last line so that it cannot merge with the first line of the next fragment
+            if !config.is_empty() && !config.ends_with(b"\n") {
+                config.push(b'\n');
+            }
         }
+        let previous = self.digests.borrow();
+        let mut changed: Vec<_> = digests.iter().filter(|(path, digest)| previous.get(*path) != Some(*digest))
+            .map(|(path, _)| path.clone()).collect();
+        changed.extend(previous.keys().filter(|path| !digests.contains_key(*path)).cloned());
+        changed.sort();
+        drop(previous);
 
         // Write the config file
-        fsext::write_atomic(config, &self.file).expect("Failed to write config file");
+        let assembly = Assembly { changed, fragments: count, size: config.len(), digest: digest(&config) };
+        self.write(config)?;
+        self.reference.borrow_mut().applied(count, confirmed, started);
+        *self.digests.borrow_mut() = digests;
+        self.metrics.assembled(started.elapsed(), assembly.fragments, assembly.size);
+
+        // Consume the commit marker of the applied transaction and the confirmation
+        transactions.consume(self.inbox.directory(), commit);
+        if confirmed {
+            transaction::consume_marker(self.inbox.directory(), CONFIRM_MARKER);
+        }
+        Ok(assembly)
     }
-}
 
+    /// Writes the config file
+    fn write(&self, config: Vec<u8>) -> Result {
+        fsext::write_atomic(&config, &self.file).context("write", &self.file)?;
+        self.generation.set(self.generation.get() + 1);
+        *self.written.borrow_mut() = config;
+        Ok(())
+    }
+}
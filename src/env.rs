@@ -0,0 +1,61 @@
+use crate::error::{ Error, Result };
+use std::{
+    env,
+    fmt::Display,
+    net::{ IpAddr, Ipv4Addr, SocketAddr },
+    str::FromStr
+};
+
+
+/// A loopback socket address for local listeners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopbackAddr(pub SocketAddr);
+impl FromStr for LoopbackAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // Accept a plain port on 127.0.0.1 or a full socket address
+        let address = match s.parse::<u16>() {
+            Ok(port) => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            Err(_) => s.parse().map_err(|_| format!("Invalid address: {} (expected port or address:port)", s))?
+        };
+        match address.ip().is_loopback() {
+            true => Ok(Self(address)),
+            false => Err(format!("Invalid address: {} (must be a loopback address)", s))
+        }
+    }
+}
+
+
+/// Reads and parses the environment variable `name` if it is set
+pub fn get<T>(name: &str) -> Result<Option<T>> where T: FromStr, T::Err: Display {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    let value = value.trim().parse().map_err(|e: T::Err| Error::Setting { name: name.to_string(), reason: e.to_string() })?;
+    Ok(Some(value))
+}
+
+
+/// Reads and parses the environment variable `name` or returns `default` if the variable is not set
+pub fn get_or<T>(name: &str, default: T) -> Result<T> where T: FromStr, T::Err: Display {
+    Ok(get(name)?.unwrap_or(default))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_addresses() {
+        let address: LoopbackAddr = "8080".parse().expect("failed to parse address");
+        assert_eq!(address.0, SocketAddr::from(([127, 0, 0, 1], 8080)));
+        let address: LoopbackAddr = "[::1]:9000".parse().expect("failed to parse address");
+        assert_eq!(address.0, "[::1]:9000".parse().expect("invalid address"));
+
+        assert!("0.0.0.0:8080".parse::<LoopbackAddr>().is_err());
+        assert!("localhost:8080".parse::<LoopbackAddr>().is_err());
+        assert!("70000".parse::<LoopbackAddr>().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fmt::{ self, Display, Formatter },
+    io,
+    path::PathBuf
+};
+
+
+/// A crate-wide result type
+pub type Result<T = ()> = std::result::Result<T, Error>;
+
+
+/// A crate-wide error
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O operation failed
+    Io {
+        /// The operation that failed
+        operation: &'static str,
+        /// The affected path (if any)
+        path: Option<PathBuf>,
+        /// The underlying error
+        error: io::Error
+    },
+    /// The fragments have been refused and the current config must be kept
+    Refused(String),
+    /// The inbox is in the middle of a transaction and the current config must be kept until it is complete
+    Pending(String),
+    /// A setting is invalid
+    Setting {
+        /// The name of the setting
+        name: String,
+        /// The reason why the setting is invalid
+        reason: String
+    },
+    /// A shared state is poisoned because a thread panicked while holding it
+    Poisoned(&'static str)
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Io { operation, path: Some(path), error } => write!(f, "failed to {} {}: {}", operation, path.display(), error),
+            Self::Io { operation, path: None, error } => write!(f, "failed to {}: {}", operation, error),
+            Self::Refused(reason) => write!(f, "refused: {}", reason),
+            Self::Pending(reason) => write!(f, "transaction pending: {}", reason),
+            Self::Setting { name, reason } => write!(f, "invalid setting {}: {}", name, reason),
+            Self::Poisoned(what) => write!(f, "{} is poisoned", what)
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+
+/// Attaches context to I/O results
+pub trait Context<T> {
+    /// Attaches the failed operation and the affected path
+    fn context<P>(self, operation: &'static str, path: P) -> Result<T> where P: Into<PathBuf>;
+    /// Attaches the failed operation
+    fn operation(self, operation: &'static str) -> Result<T>;
+}
+impl<T> Context<T> for io::Result<T> {
+    fn context<P>(self, operation: &'static str, path: P) -> Result<T> where P: Into<PathBuf> {
+        self.map_err(|error| Error::Io { operation, path: Some(path.into()), error })
+    }
+    fn operation(self, operation: &'static str) -> Result<T> {
+        self.map_err(|error| Error::Io { operation, path: None, error })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    /// A "not found" I/O error
+    fn not_found() -> io::Error {
+        io::Error::from(io::ErrorKind::NotFound)
+    }
+
+    #[test]
+    fn io_context() {
+        let error = Err::<(), _>(not_found()).context("read", "/inbox/a.cfg").expect_err("context dropped the error");
+        assert_eq!(error.to_string(), format!("failed to read /inbox/a.cfg: {}", not_found()));
+        assert!(error.source().is_some());
+
+        let error = Err::<(), _>(not_found()).operation("wait for events").expect_err("operation dropped the error");
+        assert_eq!(error.to_string(), format!("failed to wait for events: {}", not_found()));
+        assert_eq!(Ok::<_, io::Error>(7).operation("read").ok(), Some(7));
+    }
+
+    #[test]
+    fn display_errors() {
+        assert_eq!(Error::Refused("only 0 fragments left".to_string()).to_string(), "refused: only 0 fragments left");
+        assert_eq!(Error::Pending("no .commit marker".to_string()).to_string(), "transaction pending: no .commit marker");
+        let error = Error::Setting { name: "AUTOCONFD_SETTLE_MS".to_string(), reason: "invalid digit".to_string() };
+        assert_eq!(error.to_string(), "invalid setting AUTOCONFD_SETTLE_MS: invalid digit");
+        assert_eq!(Error::Poisoned("child process").to_string(), "child process is poisoned");
+        assert!(Error::Poisoned("child process").source().is_none());
+    }
+}
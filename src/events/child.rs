@@ -1,88 +1,190 @@
-use crate::events::EventSource;
+use crate::{
+    error::{ Context, Error, Result },
+    events::EventSource
+};
 use std::{
-    thread, process::Child, time::Duration,
-    sync::{
-        Arc, Mutex, mpsc::Sender,
-        atomic::{ AtomicBool, Ordering }
-    }
+    io,
+    fmt::{ self, Display, Formatter },
+    os::unix::{
+        io::{ AsRawFd, FromRawFd, OwnedFd, RawFd },
+        process::ExitStatusExt
+    },
+    process::{ Child, ExitStatus },
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant }
 };
 
 
-/// An asynchronous child process event source
-struct ChildEventSourceImpl<T> {
-    /// The managed child process
-    child: Arc<Mutex<Child>>,
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>,
-    /// The event message
-    message: T,
-    /// The event channel
-    channel: Sender<T>
+/// The exit of a child process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChildExit {
+    /// The PID of the child process
+    pub pid: u32,
+    /// The exit status
+    pub status: ExitStatus,
+    /// The time the child process has been running
+    pub uptime: Duration,
+    /// The generation of the config the child process has been running
+    pub generation: u64
+}
+impl ChildExit {
+    /// The exit code that reflects the exit status (the exit code, or `128 + signal` if the child has been killed)
+    pub fn code(&self) -> i32 {
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1
+        }
+    }
 }
-impl<T> ChildEventSourceImpl<T> where T: Clone + Send + 'static {
-    /// Creates a new asynchronous signal event source
-    pub fn start(child: Arc<Mutex<Child>>, active: Arc<AtomicBool>, message: T, channel: Sender<T>) {
-        let this = Self { child, active, message, channel };
-        thread::spawn(|| this.runloop());
+impl Display for ChildExit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (pid, uptime, generation) = (self.pid, self.uptime.as_secs_f32(), self.generation);
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _) => write!(f, "pid {} exited with code {}", pid, code)?,
+            (None, Some(signal)) => write!(f, "pid {} was terminated by signal {}", pid, signal)?,
+            (None, None) => write!(f, "pid {} exited with {}", pid, self.status)?
+        }
+        write!(f, " after {:.1}s running config generation {}", uptime, generation)
     }
+}
 
-    /// The runloop
-    fn runloop(self) {
-        // Loop as long as the event source is valid
-        'runloop: while self.active.load(Ordering::Relaxed) {
-            // Get the state of the child process
-            let exit_state = {
-                let mut child = self.child.lock().expect("Failed to lock mutex to access child process");
-                child.try_wait().expect("Failed to query child state")
-            };
-
-            // The child is dead
-            if let Some(_) = exit_state {
-                // Send the event message
-                let message = self.message.clone();
-                if let Err(_) = self.channel.send(message) {
-                    break 'runloop;
-                }
-            }
 
-            // Sleep some time
-            const SPINLOOP_INTERVAL: Duration = Duration::from_millis(100);
-            thread::sleep(SPINLOOP_INTERVAL);
+/// A child process together with its process file descriptor (`pidfd`)
+pub struct WatchedChild {
+    /// The child process
+    pub child: Child,
+    /// The process file descriptor that becomes readable if the child exits (or `None` if the exit has been reported)
+    pidfd: Option<OwnedFd>,
+    /// The point in time when the child has been started
+    started: Instant,
+    /// The point in time when the exit of the child has been observed (if it has exited)
+    stopped: Option<Instant>,
+    /// The generation of the config the child is running
+    generation: u64
+}
+impl WatchedChild {
+    /// Opens a process file descriptor for `child` which runs the config `generation`; if this fails, the child is killed
+    pub fn new(mut child: Child, generation: u64) -> Result<Self> {
+        // SAFETY: `pidfd_open` has no memory-safety preconditions
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id() as libc::pid_t, 0) };
+        if pidfd < 0 {
+            let error = io::Error::last_os_error();
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(error).operation("open process file descriptor");
+        }
+
+        // SAFETY: `pidfd` is a valid file descriptor that is owned by nobody else
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) };
+        Ok(Self { child, pidfd: Some(pidfd), started: Instant::now(), stopped: None, generation })
+    }
+
+    /// Sends `signal` to the child via its process file descriptor; returns `false` if the child has already exited
+    pub fn signal(&self, signal: libc::c_int) -> io::Result<bool> {
+        let Some(pidfd) = &self.pidfd else {
+            return Ok(false);
+        };
+
+        // SAFETY: `pidfd_send_signal` has no memory-safety preconditions if `info` is NULL
+        let null = std::ptr::null::<libc::siginfo_t>();
+        let result = unsafe { libc::syscall(libc::SYS_pidfd_send_signal, pidfd.as_raw_fd(), signal, null, 0) };
+        match result {
+            0 => Ok(true),
+            _ => match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ESRCH) => Ok(false),
+                e => Err(e)
+            }
         }
     }
+    /// The time the child has been running (until it has exited)
+    pub fn uptime(&self) -> Duration {
+        self.stopped.unwrap_or_else(Instant::now).duration_since(self.started)
+    }
 }
 
 
-/// An child process event source
+/// A child process event source that reports the exit of the child process once
 pub struct ChildEventSource {
     /// The child process
-    child: Arc<Mutex<Child>>,
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>
+    child: Arc<Mutex<WatchedChild>>
 }
 impl ChildEventSource {
     /// Creates a new child process event source
-    pub fn new(child: Arc<Mutex<Child>>) -> Self {
-        let active = Arc::new(AtomicBool::new(true));
-        Self { child, active }
+    pub fn new(child: Arc<Mutex<WatchedChild>>) -> Self {
+        Self { child }
     }
 }
-impl<T> EventSource<T> for ChildEventSource where T: Clone + Send + 'static {
-    fn event_attach(&mut self, message: T, channel: Sender<T>) {
-        self.active.store(true, Ordering::SeqCst);
-        let child = self.child.clone();
-        let active = self.active.clone();
-        ChildEventSourceImpl::start(child, active, message, channel);
-    }
-    fn event_cancel(&mut self) {
-        self.active.store(false, Ordering::SeqCst);
+impl EventSource for ChildEventSource {
+    /// The exit of the child process
+    type Event = ChildExit;
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        let Ok(child) = self.child.lock() else {
+            return Vec::new();
+        };
+        child.pidfd.iter().map(|pidfd| pidfd.as_raw_fd()).collect()
     }
-    fn event_detach(&mut self) {
-        self.active = Arc::new(AtomicBool::new(true));
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        // Reap the child if it has exited
+        let mut child = self.child.lock().map_err(|_| Error::Poisoned("child process"))?;
+        if child.pidfd.is_none() {
+            return Ok(Vec::new());
+        }
+        match child.child.try_wait().operation("query child process state")? {
+            Some(status) => {
+                // Close the process file descriptor so that the exit is only reported once
+                child.pidfd = None;
+                child.stopped = Some(Instant::now());
+                let (pid, uptime, generation) = (child.child.id(), child.uptime(), child.generation);
+                Ok(vec![ChildExit { pid, status, uptime, generation }])
+            },
+            None => Ok(Vec::new())
+        }
     }
 }
-impl Drop for ChildEventSource {
-    fn drop(&mut self) {
-        self.active.store(false, Ordering::Relaxed);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::reactor::Reactor;
+    use std::process::Command;
+
+    /// Creates a child exit with the raw wait `status`
+    fn exit(status: i32) -> ChildExit {
+        let status = ExitStatus::from_raw(status);
+        ChildExit { pid: 42, status, uptime: Duration::from_millis(1500), generation: 7 }
+    }
+
+    #[test]
+    fn exit_codes() {
+        let exited = exit(3 << 8);
+        assert_eq!(exited.code(), 3);
+        assert_eq!(exited.to_string(), "pid 42 exited with code 3 after 1.5s running config generation 7");
+
+        let killed = exit(libc::SIGKILL);
+        assert_eq!(killed.code(), 128 + libc::SIGKILL);
+        assert_eq!(killed.to_string(), "pid 42 was terminated by signal 9 after 1.5s running config generation 7");
+    }
+
+    #[test]
+    fn exits_are_reported_once() {
+        let child = Command::new("sh").args(["-c", "exit 3"]).spawn().expect("failed to spawn child");
+        let child = Arc::new(Mutex::new(WatchedChild::new(child, 7).expect("failed to watch child")));
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        reactor.attach(ChildEventSource::new(child.clone()), Some, None);
+
+        // The exit is reported once, and the child can no longer be signalled
+        let exits = reactor.wait().expect("failed to wait");
+        let [Some(exit)] = exits.as_slice() else {
+            panic!("unexpected events: {:?}", exits);
+        };
+        assert_eq!((exit.code(), exit.generation), (3, 7));
+        let mut source = ChildEventSource::new(child.clone());
+        assert!(source.event_poll().expect("failed to poll child").is_empty());
+        let child = child.lock().expect("failed to lock child");
+        assert!(!child.signal(libc::SIGTERM).expect("failed to signal child"));
+        assert_eq!(child.uptime(), exit.uptime);
     }
 }
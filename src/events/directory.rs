@@ -1,135 +1,282 @@
 use crate::{
+    backoff::Backoff,
+    error::{ Context, Error, Result },
     events::EventSource,
-    fsext::{ self, FilePattern }
+    fsext::{ self, FilePattern },
+    guard::CONFIRM_MARKER,
+    inbox::Inbox,
+    log,
+    transaction::{ MarkerId, TransactionMode, COMMIT_MARKER, LOCK_MARKER }
 };
 use sha2::{ Sha512, Digest };
 use std::{
-    fs, thread, path::PathBuf, time::Duration,
-    sync::{
-        Arc, mpsc::Sender,
-        atomic::{ AtomicBool, Ordering }
-    }
+    cell::RefCell, ffi::CString, io,
+    os::unix::io::{ AsRawFd, FromRawFd, OwnedFd, RawFd },
+    path::Path,
+    rc::Rc,
+    time::{ Duration, Instant }
 };
 
 
-/// An asynchronous directory monitor event source
-struct DirectoryEventSourceImpl<T, P> {
-    /// The directory to monitor
-    directory: PathBuf,
-    /// The file pattern to match
-    pattern: P,
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>,
-    /// The event message
-    message: T,
-    /// The event channel
-    channel: Sender<T>
+/// The inotify events that indicate a change within a watched directory
+///
+/// # Note
+/// `IN_MODIFY` is left out since it fires for every single write; a written file is picked up once it is closed.
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_CLOSE_WRITE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+    | libc::IN_ATTRIB | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_ONLYDIR;
+
+
+/// An inotify instance that watches the inbox and the trusted keys directory
+struct Watches {
+    /// The inotify file descriptor
+    fd: OwnedFd
 }
-impl<T, P> DirectoryEventSourceImpl<T, P> where T: Clone + Send + 'static, P: FilePattern {
-    /// Creates a new asynchronous signal event source
-    pub fn start<D>(directory: D, pattern: P, active: Arc<AtomicBool>, message: T, channel: Sender<T>)
-        where D: Into<PathBuf>, P: Send + 'static
-    {
-        let this = Self { directory: directory.into(), pattern, active, message, channel };
-        thread::spawn(|| this.runloop());
+impl Watches {
+    /// Creates a new inotify instance that watches the inbox and the trusted keys directory (if any)
+    pub fn new<P>(inbox: &Inbox<P>) -> Result<Self> {
+        // SAFETY: `inotify_init1` has no memory-safety preconditions
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).operation("create inotify instance");
+        }
+
+        // SAFETY: `fd` is a valid file descriptor that is owned by nobody else
+        let this = Self { fd: unsafe { OwnedFd::from_raw_fd(fd) } };
+        this.watch(inbox.directory())?;
+        if let Some(trusted_keys) = inbox.trusted_keys() {
+            // A missing keys directory is reported by the scan, so the inbox remains watchable
+            let _ = this.watch(trusted_keys.directory());
+        }
+        Ok(this)
     }
 
-    /// The runloop
-    fn runloop(self) {
-        // Get the current directory hash
-        let mut current_dirhash = self.dirhash();
-
-        // Loop as long as the event source is valid
-        'runloop: while self.active.load(Ordering::Relaxed) {
-            // Check the current dirhash
-            let dirhash = self.dirhash();
-            if dirhash != current_dirhash {
-                // Send the eveent message
-                let message = self.message.clone();
-                if let Err(_) = self.channel.send(message) {
-                    break 'runloop;
+    /// Reads all pending events and returns whether the watches are still intact
+    pub fn drain(&self) -> Result<bool> {
+        let mut intact = true;
+        let mut buf = [0u8; 4096];
+        loop {
+            // Read the next batch of events
+            // SAFETY: `buf` is a valid buffer of `buf.len()` bytes
+            let read = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            let read = match read {
+                read if read >= 0 => read as usize,
+                _ => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::WouldBlock => return Ok(intact),
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e).operation("read inotify events")
                 }
-            }
-            current_dirhash = dirhash;
+            };
 
-            // Sleep some time
-            const SPINLOOP_INTERVAL: Duration = Duration::from_millis(1500);
-            thread::sleep(SPINLOOP_INTERVAL);
+            // Check whether a watch has been removed (e.g. because the directory has been deleted or unmounted)
+            let mut offset = 0;
+            while offset + std::mem::size_of::<libc::inotify_event>() <= read {
+                // SAFETY: the kernel only writes complete events and the buffer contains at least one more event header
+                let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+                let broken = libc::IN_IGNORED | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_UNMOUNT;
+                intact &= event.mask & broken == 0;
+                offset += std::mem::size_of::<libc::inotify_event>() + event.len as usize;
+            }
         }
     }
 
-    /// Recursively computes a hash over all files within `directory` whose names match `pattern`
-    fn dirhash(&self) -> Vec<u8> {
-        // List and sort the entries
-        let mut files: Vec<_> = fsext::list_files(&self.directory).expect("Failed to list directory")
-            .into_iter().map(|p| p.canonicalize().expect("Failed to canonicalize path"))
-            .collect();
-        files.sort();
-        
-        // Hash the entries
-        let mut sha512 = Sha512::new();
-        'read_loop: for path in files {
-            // Check if the path matches the pattern
-            let name = path.components().last().expect("Path has no last component");
-            let name_bytes = fsext::path_bytes(&name);
-            if !self.pattern.matches(&name_bytes) {
-                continue 'read_loop;
-            }
-            
-            // Hash the filename
-            let path_bytes = fsext::path_bytes(&path);
-            sha512.update(&path_bytes);
-            sha512.update(&path_bytes.len().to_be_bytes());
-
-            // Read and hash the file
-            let data = fs::read(&path).expect("Failed to read file");
-            sha512.update(&data);
-            sha512.update(&data.len().to_be_bytes());
+    /// Adds a watch for `directory`
+    fn watch(&self, directory: &Path) -> Result {
+        let path = CString::new(fsext::path_bytes(directory))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e)).context("watch", directory)?;
+        // SAFETY: `path` is a valid NUL-terminated string
+        let watch = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), WATCH_MASK) };
+        match watch {
+            watch if watch >= 0 => Ok(()),
+            _ => Err(io::Error::last_os_error()).context("watch", directory)
         }
-        Vec::from(sha512.finalize().as_slice())
     }
 }
 
 
+/// The last applied state of the inbox
+#[derive(Debug, Clone, Default)]
+struct Applied {
+    /// The hash over the last applied fragments
+    dirhash: Option<Vec<u8>>,
+    /// The last applied commit marker
+    commit: Option<MarkerId>,
+    /// The last seen confirmation marker
+    confirm: Option<MarkerId>
+}
+
+
+/// The last applied state of the inbox that outlives a restarted [`DirectoryEventSource`], so that changes made while it
+/// was not running are picked up (shared between all clones)
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+    /// The last applied state (or `None` if it has not been determined yet)
+    applied: Rc<RefCell<Option<Applied>>>
+}
+
+
 /// A directory monitor event source
-/// 
+///
 /// # Note
-/// This is an el-cheapo implementation that is useful to periodically scan (1.5 seconds) if e.g. a configuration has
-/// changed. It is NOT suitable for directories containing large files or persistent monitoring.
+/// The inbox and the trusted keys directory are watched via inotify. Fragments that are still being written are
+/// re-checked once their settle window has passed; if the inbox is unavailable, it is re-checked with an exponential
+/// backoff and watched again once it reappears.
 pub struct DirectoryEventSource<P> {
-    /// The directory to monitor
-    directory: PathBuf,
-    /// The pattern
-    pattern: P,
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>
+    /// The inbox to monitor
+    inbox: Inbox<P>,
+    /// The active watches (or `None` if the inbox is currently not watchable)
+    watches: Option<Watches>,
+    /// The backoff while the inbox is unavailable
+    backoff: Backoff,
+    /// The point in time at which the inbox should be checked again
+    deadline: Option<Instant>,
+    /// The last applied state of the inbox
+    baseline: Baseline
 }
-impl<P> DirectoryEventSource<P> {
-    /// Creates a new FS monitor for a given directory
-    pub fn new<D>(directory: D, pattern: P) -> Self where D: Into<PathBuf>, P: FilePattern {
-        let active = Arc::new(AtomicBool::new(true));
-        Self { directory: directory.into(), pattern, active }
+impl<P> DirectoryEventSource<P> where P: FilePattern {
+    /// The initial retry interval while the inbox is unavailable
+    const RETRY_INTERVAL: Duration = Duration::from_millis(1500);
+    /// The maximum retry interval while the inbox is unavailable
+    const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// Creates a new FS monitor for a given inbox; the `baseline` is taken over from a previous instance (if any)
+    pub fn new(inbox: Inbox<P>, baseline: Baseline) -> Self {
+        // Get the current directory hash, commit marker and confirmation marker unless they are already known
+        let backoff = Backoff::new(Self::RETRY_INTERVAL, Self::MAX_RETRY_INTERVAL);
+        if baseline.applied.borrow().is_none() {
+            let dirhash = Self::dirhash(&inbox).ok();
+            let commit = TransactionMode::marker(inbox.directory(), COMMIT_MARKER);
+            let confirm = TransactionMode::marker(inbox.directory(), CONFIRM_MARKER);
+            *baseline.applied.borrow_mut() = Some(Applied { dirhash, commit, confirm });
+        }
+
+        // Install the watches on the first poll
+        Self { inbox, watches: None, backoff, deadline: Some(Instant::now()), baseline }
+    }
+
+    /// Checks the inbox and returns whether it has changed
+    fn check(&mut self) -> bool {
+        // (Re-)install the watches and compute the directory hash; back off while the inbox is unavailable
+        let dirhash = match self.watch().and_then(|_| Self::dirhash(&self.inbox)) {
+            Ok(dirhash) => Some(dirhash),
+            // Represent a refused scan by an empty hash, so that the reload reports the refusal once it occurs
+            Err(Error::Refused(_)) => Some(Vec::new()),
+            Err(e) => {
+                let delay = self.backoff.next();
+                log::warn("inbox_unavailable", format!("Cannot watch inbox: {}; retrying in {}s", e, delay.as_secs_f32()))
+                    .field("retry_s", delay.as_secs_f64()).error(e).emit();
+                self.watches = None;
+                self.deadline = Some(Instant::now() + delay);
+                return false;
+            }
+        };
+        if self.backoff.is_active() {
+            log::info("inbox_available", "Inbox is available again").emit();
+            self.backoff.reset();
+        }
+
+        // Check for changes depending on the transaction mode
+        let mut applied = self.baseline.applied.borrow_mut();
+        let applied = applied.get_or_insert_with(Applied::default);
+        let directory = self.inbox.directory();
+        let commit = TransactionMode::marker(directory, COMMIT_MARKER);
+        let changed = match self.inbox.transactions() {
+            TransactionMode::Off => dirhash != applied.dirhash,
+            TransactionMode::Lock => {
+                let locked = TransactionMode::marker(directory, LOCK_MARKER).is_some();
+                !locked && !self.inbox.is_settling() && dirhash != applied.dirhash
+            },
+            TransactionMode::Commit => commit.is_some() && commit != applied.commit && !self.inbox.is_settling()
+        };
+
+        // A new confirmation always triggers an event to apply a change that has been blocked by the deletion guard
+        let confirm = TransactionMode::marker(directory, CONFIRM_MARKER);
+        let changed = changed || (confirm.is_some() && confirm != applied.confirm);
+        applied.confirm = confirm;
+
+        // Re-check once the next fragment has settled
+        self.deadline = self.inbox.settle_deadline();
+        if changed {
+            (applied.dirhash, applied.commit) = (dirhash, commit);
+        }
+        changed
+    }
+
+    /// Installs the watches if necessary
+    fn watch(&mut self) -> Result {
+        let intact = match &self.watches {
+            Some(watches) => watches.drain()?,
+            None => false
+        };
+        if !intact {
+            self.watches = Some(Watches::new(&self.inbox)?);
+        }
+        Ok(())
+    }
+
+    /// Computes a hash over all fragments within the inbox
+    fn dirhash(inbox: &Inbox<P>) -> Result<Vec<u8>> {
+        let mut sha512 = Sha512::new();
+        for fragment in inbox.fragments()? {
+            // Hash the filename
+            let path_bytes = fsext::path_bytes(&fragment.path);
+            sha512.update(&path_bytes);
+            sha512.update(path_bytes.len().to_be_bytes());
+
+            // Hash the tenant
+            let tenant = fragment.tenant.unwrap_or_default();
+            sha512.update(tenant.as_bytes());
+            sha512.update(tenant.len().to_be_bytes());
+
+            // Hash the file
+            sha512.update(&fragment.data);
+            sha512.update(fragment.data.len().to_be_bytes());
+        }
+        Ok(sha512.finalize().to_vec())
     }
 }
-impl<T, P> EventSource<T> for DirectoryEventSource<P>
-    where T: Clone + Send + 'static, P: FilePattern + Clone + Send + 'static
-{
-    fn event_attach(&mut self, message: T, channel: Sender<T>) {
-        self.active.store(true, Ordering::SeqCst);
-        let directory = self.directory.clone();
-        let pattern = self.pattern.clone();
-        let active = self.active.clone();
-        DirectoryEventSourceImpl::start(directory, pattern, active, message, channel);
+impl<P> EventSource for DirectoryEventSource<P> where P: FilePattern {
+    /// A change within the inbox
+    type Event = ();
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        self.watches.iter().map(|watches| watches.fd.as_raw_fd()).collect()
     }
-    fn event_cancel(&mut self) {
-        self.active.store(false, Ordering::SeqCst);
+    fn event_deadline(&self) -> Option<Instant> {
+        self.deadline
     }
-    fn event_detach(&mut self) {
-        self.active = Arc::new(AtomicBool::new(true));
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        match self.check() {
+            true => Ok(vec![()]),
+            false => Ok(Vec::new())
+        }
     }
 }
-impl<P> Drop for DirectoryEventSource<P> {
-    fn drop(&mut self) {
-        self.active.store(false, Ordering::Relaxed);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext::FileExtensionPattern;
+    use std::fs;
+
+    #[test]
+    fn changes_while_restarting_are_picked_up() {
+        let directory = fsext::test_dir("directory-baseline");
+        fs::write(directory.join("a.cfg"), "a").expect("failed to write fragment");
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg"));
+        let baseline = Baseline::default();
+        let mut source = DirectoryEventSource::new(inbox.clone(), baseline.clone());
+        assert!(!source.check());
+
+        // A change while no event source is running is reported by the restarted event source
+        drop(source);
+        fs::write(directory.join("b.cfg"), "b").expect("failed to write fragment");
+        let mut source = DirectoryEventSource::new(inbox.clone(), baseline.clone());
+        assert!(source.check());
+        assert!(!source.check());
+
+        // A new baseline starts from the current state
+        fs::write(directory.join("c.cfg"), "c").expect("failed to write fragment");
+        assert!(!DirectoryEventSource::new(inbox, Baseline::default()).check());
     }
 }
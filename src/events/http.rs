@@ -0,0 +1,455 @@
+use crate::{
+    error::{ Context, Result },
+    events::EventSource,
+    log
+};
+use std::{
+    cell::RefCell,
+    fs::{ self, File },
+    io::{ self, Read, Write },
+    net::{ SocketAddr, TcpListener, TcpStream },
+    os::unix::{
+        fs::{ FileTypeExt, PermissionsExt },
+        io::{ AsRawFd, RawFd },
+        net::{ UnixListener, UnixStream }
+    },
+    path::Path,
+    rc::Rc,
+    time::{ Duration, Instant }
+};
+
+
+/// The maximum size of a request (header and body)
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+/// The time a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The time a client may take to receive the response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The maximum amount of pending connections and of pending responses
+const MAX_CONNECTIONS: usize = 64;
+
+
+/// A listening socket
+#[derive(Debug)]
+enum Listener {
+    /// A TCP listener
+    Tcp(TcpListener),
+    /// A Unix stream listener
+    Unix(UnixListener)
+}
+impl Listener {
+    /// Accepts a pending connection
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Self::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream))
+        }
+    }
+    /// The file descriptor of the listener
+    pub fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(listener) => listener.as_raw_fd(),
+            Self::Unix(listener) => listener.as_raw_fd()
+        }
+    }
+}
+
+
+/// A connection
+#[derive(Debug)]
+enum Stream {
+    /// A TCP connection
+    Tcp(TcpStream),
+    /// A Unix stream connection
+    Unix(UnixStream)
+}
+impl Stream {
+    /// Sets the non-blocking mode
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Self::Unix(stream) => stream.set_nonblocking(nonblocking)
+        }
+    }
+    /// The file descriptor of the connection
+    pub fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Self::Tcp(stream) => stream.as_raw_fd(),
+            Self::Unix(stream) => stream.as_raw_fd()
+        }
+    }
+}
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf)
+        }
+    }
+}
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Unix(stream) => stream.write(buf)
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Unix(stream) => stream.flush()
+        }
+    }
+}
+
+
+/// A response that is being sent
+#[derive(Debug)]
+struct Response {
+    /// The connection
+    stream: Stream,
+    /// The response data
+    data: Vec<u8>,
+    /// The amount of bytes that have been sent
+    sent: usize,
+    /// The point in time at which the connection is dropped if the response has not been sent completely
+    deadline: Instant
+}
+impl Response {
+    /// Sends as much pending data as possible without blocking
+    ///
+    /// # Note
+    /// Returns `Ok(true)` if the response has been sent completely or an error if the connection must be dropped.
+    fn send(&mut self) -> io::Result<bool> {
+        while self.sent < self.data.len() {
+            match self.stream.write(&self.data[self.sent..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(written) => self.sent += written,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(true)
+    }
+}
+
+
+/// The state that is shared between all clones of an event source and its requests
+#[derive(Debug)]
+struct Shared {
+    /// The listeners
+    listeners: Vec<Listener>,
+    /// A reserved file descriptor that is released to reject connections if the file descriptors are exhausted
+    reserve: RefCell<Option<File>>,
+    /// The responses that are being sent
+    responses: RefCell<Vec<Response>>
+}
+
+
+/// An HTTP request
+#[derive(Debug)]
+pub struct Request {
+    /// The request method
+    pub method: String,
+    /// The request path (without the query)
+    pub path: String,
+    /// The request body
+    pub body: Vec<u8>,
+    /// The connection to respond on
+    stream: Stream,
+    /// The state of the event source that sends the response
+    shared: Rc<Shared>
+}
+impl Request {
+    /// Sends the response and closes the connection
+    ///
+    /// # Note
+    /// The response is sent without blocking; if the client does not receive it immediately, the remainder is sent by the
+    /// event source once the connection becomes writable, or the connection is dropped after a timeout.
+    pub fn respond(self, status: u16, content_type: &str, body: &[u8]) {
+        let reason = match status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error"
+        };
+        let header = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status, reason, content_type, body.len()
+        );
+
+        // Send as much as possible and queue the remainder
+        let data = [header.as_bytes(), body].concat();
+        let mut response = Response { stream: self.stream, data, sent: 0, deadline: Instant::now() + RESPONSE_TIMEOUT };
+        match response.send() {
+            Ok(true) => (),
+            Ok(false) => {
+                // Drop the oldest response if there are too many
+                let mut responses = self.shared.responses.borrow_mut();
+                if responses.len() >= MAX_CONNECTIONS {
+                    responses.remove(0);
+                }
+                responses.push(response);
+            },
+            Err(e) => log::debug("http", format!("Failed to send HTTP response: {}", e)).error(e).emit()
+        }
+    }
+}
+
+
+/// A connection whose request is being received
+struct Connection {
+    /// The connection
+    stream: Stream,
+    /// The received data
+    buffer: Vec<u8>,
+    /// The point in time at which the connection is dropped if the request is incomplete
+    deadline: Instant
+}
+impl Connection {
+    /// Reads the pending data and parses the request if it is complete
+    ///
+    /// # Note
+    /// Returns `Ok(None)` if the request is incomplete or an error if the connection must be dropped.
+    fn read(&mut self) -> io::Result<Option<(String, String, Vec<u8>)>> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+            if self.buffer.len() > MAX_REQUEST_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "request is too large"));
+            }
+        }
+        Self::parse(&self.buffer)
+    }
+
+    /// Parses a complete request into method, path and body
+    fn parse(buffer: &[u8]) -> io::Result<Option<(String, String, Vec<u8>)>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid request");
+        let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return Ok(None);
+        };
+
+        // Parse the request line and the content length
+        let header = std::str::from_utf8(&buffer[..end]).map_err(|_| invalid())?;
+        let mut lines = header.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+            return Err(invalid());
+        };
+        let length = lines.filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+            .map(|(_, value)| value.trim().parse::<usize>().map_err(|_| invalid())).transpose()?.unwrap_or_default();
+
+        // Wait for the body
+        let body = &buffer[end + 4..];
+        if body.len() < length {
+            return Ok(None);
+        }
+        let path = target.split('?').next().unwrap_or_default();
+        Ok(Some((method.to_string(), path.to_string(), body[..length].to_vec())))
+    }
+}
+
+
+/// A minimal HTTP/1.1 server event source that reports each complete request
+///
+/// # Note
+/// Each connection serves a single request and is closed after the response. All clones share the same listeners and
+/// pending responses, so the event source can be reattached without rebinding the listeners; pending connections are not
+/// cloned.
+pub struct HttpEventSource {
+    /// The listeners and pending responses (shared between all clones)
+    shared: Rc<Shared>,
+    /// The connections whose requests are being received
+    connections: Vec<Connection>
+}
+impl HttpEventSource {
+    /// Binds a Unix stream listener at `path` (replacing a stale socket) that is only accessible by the owner, and/or a TCP
+    /// listener at `tcp`
+    pub fn bind(path: Option<&Path>, tcp: Option<SocketAddr>) -> Result<Self> {
+        let mut listeners = Vec::new();
+        if let Some(path) = path {
+            // Bind the Unix stream listener
+            if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                fs::remove_file(path).context("remove stale HTTP socket", path)?;
+            }
+            let listener = UnixListener::bind(path).context("bind HTTP socket", path)?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600)).context("restrict HTTP socket", path)?;
+            listener.set_nonblocking(true).context("configure HTTP socket", path)?;
+            listeners.push(Listener::Unix(listener));
+        }
+        if let Some(address) = tcp {
+            // Bind the TCP listener
+            let listener = TcpListener::bind(address).operation("bind HTTP listener")?;
+            listener.set_nonblocking(true).operation("configure HTTP listener")?;
+            listeners.push(Listener::Tcp(listener));
+        }
+        let reserve = File::open("/dev/null").operation("reserve file descriptor")?;
+        let shared = Shared { listeners, reserve: RefCell::new(Some(reserve)), responses: RefCell::new(Vec::new()) };
+        Ok(Self { shared: Rc::new(shared), connections: Vec::new() })
+    }
+
+    /// Accepts all pending connections of `listener`
+    fn accept(&mut self, listener: &Listener) -> io::Result<()> {
+        loop {
+            let stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) => {
+                    log::warn("http", format!("Rejecting HTTP connection: {}", e)).error(e).emit();
+                    self.reject(listener);
+                    return Ok(());
+                },
+                Err(e) => return Err(e)
+            };
+
+            // Drop the oldest connection if there are too many
+            if self.connections.len() >= MAX_CONNECTIONS {
+                self.connections.remove(0);
+            }
+            stream.set_nonblocking(true)?;
+            let deadline = Instant::now() + REQUEST_TIMEOUT;
+            self.connections.push(Connection { stream, buffer: Vec::new(), deadline });
+        }
+    }
+    /// Releases the reserved file descriptor to accept and immediately close a pending connection of `listener`, so that
+    /// the listener does not remain readable while the file descriptors are exhausted
+    fn reject(&self, listener: &Listener) {
+        let mut reserve = self.shared.reserve.borrow_mut();
+        drop(reserve.take());
+        drop(listener.accept());
+        *reserve = File::open("/dev/null").ok();
+    }
+
+    /// Sends the pending responses and drops completed, failed or timed out responses
+    fn flush(&self) {
+        let now = Instant::now();
+        self.shared.responses.borrow_mut().retain_mut(|response| match response.send() {
+            Ok(false) => response.deadline > now,
+            Ok(true) | Err(_) => false
+        });
+    }
+}
+impl Clone for HttpEventSource {
+    fn clone(&self) -> Self {
+        Self { shared: self.shared.clone(), connections: Vec::new() }
+    }
+}
+impl EventSource for HttpEventSource {
+    /// A complete request
+    type Event = Request;
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        let listeners = self.shared.listeners.iter().map(|listener| listener.as_raw_fd());
+        listeners.chain(self.connections.iter().map(|connection| connection.stream.as_raw_fd())).collect()
+    }
+    fn event_write_fds(&self) -> Vec<RawFd> {
+        self.shared.responses.borrow().iter().map(|response| response.stream.as_raw_fd()).collect()
+    }
+    fn event_deadline(&self) -> Option<Instant> {
+        let responses = self.shared.responses.borrow();
+        let deadlines = responses.iter().map(|response| response.deadline);
+        self.connections.iter().map(|connection| connection.deadline).chain(deadlines).min()
+    }
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        // Send the pending responses
+        self.flush();
+
+        // Accept new connections
+        let shared = self.shared.clone();
+        for listener in &shared.listeners {
+            self.accept(listener).operation("accept HTTP connection")?;
+        }
+
+        // Read the pending requests and drop failed or timed out connections
+        let (now, mut requests, mut index) = (Instant::now(), Vec::new(), 0);
+        while index < self.connections.len() {
+            let connection = &mut self.connections[index];
+            match connection.read() {
+                Ok(Some((method, path, body))) => {
+                    let connection = self.connections.remove(index);
+                    let shared = self.shared.clone();
+                    requests.push(Request { method, path, body, stream: connection.stream, shared });
+                },
+                Ok(None) if connection.deadline > now => index += 1,
+                Ok(None) | Err(_) => drop(self.connections.remove(index))
+            }
+        }
+        Ok(requests)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext;
+
+    /// Parses `request` into method, path and body
+    fn parse(request: &[u8]) -> io::Result<Option<(String, String, Vec<u8>)>> {
+        Connection::parse(request)
+    }
+
+    #[test]
+    fn requests() {
+        let request = parse(b"GET /metrics?format=text HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let request = request.expect("failed to parse request");
+        assert_eq!(request, Some(("GET".to_string(), "/metrics".to_string(), Vec::new())));
+
+        let request = parse(b"POST /reload HTTP/1.1\r\ncontent-LENGTH: 4\r\n\r\nbodyextra");
+        let request = request.expect("failed to parse request");
+        assert_eq!(request, Some(("POST".to_string(), "/reload".to_string(), b"body".to_vec())));
+    }
+
+    #[test]
+    fn incomplete_requests() {
+        assert!(parse(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n").expect("failed to parse request").is_none());
+        assert!(parse(b"POST /reload HTTP/1.1\r\nContent-Length: 4\r\n\r\nbo").expect("failed to parse request").is_none());
+    }
+
+    #[test]
+    fn invalid_requests() {
+        assert!(parse(b"GET\r\n\r\n").is_err());
+        assert!(parse(b"POST /reload HTTP/1.1\r\nContent-Length: four\r\n\r\n").is_err());
+        assert!(parse(b"GET /\xff HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn serve_over_unix_socket() {
+        let dir = fsext::test_dir("http");
+        let path = dir.join("http.sock");
+        let mut source = HttpEventSource::bind(Some(&path), None).expect("failed to bind HTTP socket");
+        let mode = fs::metadata(&path).expect("failed to stat HTTP socket").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Send a request and wait until it is complete
+        let mut client = UnixStream::connect(&path).expect("failed to connect to HTTP socket");
+        client.write_all(b"GET /config HTTP/1.1\r\n\r\n").expect("failed to send request");
+        let request = loop {
+            if let Some(request) = source.event_poll().expect("failed to poll HTTP socket").pop() {
+                break request;
+            }
+        };
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/config"));
+
+        // The response closes the connection
+        request.respond(200, "text/plain", b"config\n");
+        let mut response = String::new();
+        client.read_to_string(&mut response).expect("failed to read response");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("Content-Length: 7\r\nConnection: close\r\n\r\nconfig\n"));
+    }
+}
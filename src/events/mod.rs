@@ -1,16 +1,39 @@
 pub mod child;
 pub mod signals;
+pub mod syslog;
 pub mod directory;
+pub mod http;
+pub mod output;
+pub mod reactor;
+pub mod timer;
 
-use std::sync::mpsc::Sender;
+use crate::error::Result;
+use std::{ os::unix::io::RawFd, time::Instant };
 
 
-/// An asynchronous event source
-pub trait EventSource<T> where T: Clone + Send + 'static {
-    /// Attaches a new event producer that sends `message` over `channel` if an event occurrs
-    fn event_attach(&mut self, message: T, channel: Sender<T>);
-    /// Cancels all attached event producers
-    fn event_cancel(&mut self);
-    /// Detaches all attached event producers (i.e. the event source will not be canceled if it is dropped)
-    fn event_detach(&mut self);
+/// An event source that is driven by the [`reactor::Reactor`]
+pub trait EventSource {
+    /// The event type
+    type Event;
+
+    /// The file descriptors that become readable if the event source has pending events
+    ///
+    /// # Note
+    /// The file descriptors are (re-)registered with the reactor whenever they change, so an event source may replace its
+    /// file descriptors at any time; a replacement must be opened before the old file descriptor is closed so that its
+    /// number differs.
+    fn event_fds(&self) -> Vec<RawFd>;
+    /// The file descriptors that become writable if the event source can make progress with pending output
+    ///
+    /// # Note
+    /// Like [`Self::event_fds`], but registered for writability instead; a file descriptor must not be in both lists.
+    fn event_write_fds(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+    /// The point in time at which the event source must be polled even if its file descriptor is not readable (if any)
+    fn event_deadline(&self) -> Option<Instant> {
+        None
+    }
+    /// Processes the pending events
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>>;
 }
@@ -0,0 +1,261 @@
+use crate::{
+    error::{ Context, Error, Result },
+    events::EventSource,
+    log
+};
+use std::{
+    io::{ self, Read },
+    fmt::{ self, Display, Formatter },
+    fs::File,
+    os::unix::io::{ AsRawFd, OwnedFd, RawFd },
+    process::Child,
+    sync::{ Arc, Mutex }
+};
+
+
+/// The maximum length of a single output line; longer lines are split
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+
+/// An output stream of a child process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// The standard output
+    Stdout,
+    /// The standard error
+    Stderr
+}
+impl Display for Stream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr")
+        }
+    }
+}
+
+
+/// A line of output of a child process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildOutput {
+    /// The stream the line has been written to
+    pub stream: Stream,
+    /// The PID of the child process
+    pub pid: u32,
+    /// The generation of the config the child process has been running
+    pub generation: u64,
+    /// The line without the trailing newline
+    pub line: String
+}
+impl Display for ChildOutput {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "pid {} (config generation {}) {}: {}", self.pid, self.generation, self.stream, self.line)
+    }
+}
+
+
+/// A non-blocking pipe connected to an output stream of a child process
+struct Pipe {
+    /// The read end of the pipe
+    file: File,
+    /// The stream the pipe is connected to
+    stream: Stream,
+    /// The PID of the child process
+    pid: u32,
+    /// The generation of the config the child process is running
+    generation: u64,
+    /// The incomplete trailing line
+    buffer: Vec<u8>
+}
+impl Pipe {
+    /// Wraps the read end `fd` of a pipe connected to `stream` of the child process `pid`
+    pub fn new(fd: OwnedFd, stream: Stream, pid: u32, generation: u64) -> Result<Self> {
+        // Make the pipe non-blocking
+        // SAFETY: `fcntl` has no memory-safety preconditions
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        // SAFETY: `fcntl` has no memory-safety preconditions
+        if flags < 0 || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error()).operation("configure output pipe");
+        }
+        Ok(Self { file: File::from(fd), stream, pid, generation, buffer: Vec::new() })
+    }
+
+    /// Reads the pending output and appends the complete lines to `lines`; returns `false` if the pipe has been closed
+    ///
+    /// # Note
+    /// At most [`MAX_LINE_LEN`] bytes are read at once so that a chatty child process cannot starve the other event
+    /// sources; the remaining output is read during the next poll.
+    pub fn read(&mut self, lines: &mut Vec<ChildOutput>) -> io::Result<bool> {
+        let mut chunk = [0; 4096];
+        for _ in 0..MAX_LINE_LEN / chunk.len() {
+            // Read the next chunk or flush the incomplete line if the pipe has been closed
+            let read = match self.file.read(&mut chunk) {
+                Ok(0) => {
+                    if !self.buffer.is_empty() {
+                        let line = std::mem::take(&mut self.buffer);
+                        lines.push(self.line(&line));
+                    }
+                    return Ok(false);
+                },
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+
+            // Split the complete lines
+            self.buffer.extend_from_slice(&chunk[..read]);
+            while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<_> = self.buffer.drain(..=end).collect();
+                lines.push(self.line(&line[..end]));
+            }
+            if self.buffer.len() >= MAX_LINE_LEN {
+                let line: Vec<_> = self.buffer.drain(..MAX_LINE_LEN).collect();
+                lines.push(self.line(&line));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Creates an output line
+    fn line(&self, line: &[u8]) -> ChildOutput {
+        let line = String::from_utf8_lossy(line).trim_end_matches('\r').to_string();
+        ChildOutput { stream: self.stream, pid: self.pid, generation: self.generation, line }
+    }
+}
+
+
+/// A child process output event source that reports each line written to the captured stdout and stderr
+///
+/// # Note
+/// All clones share the same pipes, so a clone can be attached to the reactor while another clone captures the output of
+/// newly spawned child processes. The pipes of exited child processes are drained until they are closed.
+#[derive(Clone, Default)]
+pub struct OutputEventSource {
+    /// The pipes of all child processes whose output is captured
+    pipes: Arc<Mutex<Vec<Pipe>>>
+}
+impl OutputEventSource {
+    /// Creates a new output event source without pipes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Captures the piped stdout and stderr of `child` which runs the config `generation`
+    pub fn capture(&self, child: &mut Child, generation: u64) -> Result {
+        let pid = child.id();
+        let stdout = child.stdout.take().map(|stdout| Pipe::new(stdout.into(), Stream::Stdout, pid, generation));
+        let stderr = child.stderr.take().map(|stderr| Pipe::new(stderr.into(), Stream::Stderr, pid, generation));
+
+        let mut pipes = self.pipes.lock().map_err(|_| Error::Poisoned("child process output"))?;
+        for pipe in stdout.into_iter().chain(stderr) {
+            pipes.push(pipe?);
+        }
+        Ok(())
+    }
+}
+impl EventSource for OutputEventSource {
+    /// A line of output
+    type Event = ChildOutput;
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        let Ok(pipes) = self.pipes.lock() else {
+            return Vec::new();
+        };
+        pipes.iter().map(|pipe| pipe.file.as_raw_fd()).collect()
+    }
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        // Read all pipes and drop the closed ones
+        let mut pipes = self.pipes.lock().map_err(|_| Error::Poisoned("child process output"))?;
+        let mut lines = Vec::new();
+        let mut index = 0;
+        while index < pipes.len() {
+            match pipes[index].read(&mut lines) {
+                Ok(true) => index += 1,
+                Ok(false) => drop(pipes.remove(index)),
+                Err(e) => {
+                    let pid = pipes[index].pid;
+                    log::warn("haproxy_output", format!("Failed to read output of pid {}: {}", pid, e))
+                        .field("pid", pid).error(e).emit();
+                    drop(pipes.remove(index));
+                }
+            }
+        }
+        Ok(lines)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::reactor::Reactor;
+    use std::{ io::Write, os::unix::io::FromRawFd, process::{ Command, Stdio }, thread };
+
+    /// Creates a pipe connected to stdout and returns its reader and writer
+    fn pipe() -> (Pipe, File) {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` is a valid buffer for two file descriptors
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        // SAFETY: the file descriptors have just been created and are not owned by anything else
+        let (reader, writer) = unsafe { (OwnedFd::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        (Pipe::new(reader, Stream::Stdout, 42, 7).expect("failed to create pipe"), writer)
+    }
+
+    /// Gets the text of `lines`
+    fn text(lines: &[ChildOutput]) -> Vec<&str> {
+        lines.iter().map(|output| output.line.as_str()).collect()
+    }
+
+    #[test]
+    fn lines_are_split() {
+        let (mut pipe, mut writer) = pipe();
+        let mut lines = Vec::new();
+
+        // Complete lines are reported without their line endings, the incomplete line is kept
+        writer.write_all(b"one\r\ntwo\nthr").expect("failed to write to pipe");
+        assert!(pipe.read(&mut lines).expect("failed to read pipe"));
+        assert_eq!(text(&lines), ["one", "two"]);
+        assert_eq!(lines[0].to_string(), "pid 42 (config generation 7) stdout: one");
+
+        // The incomplete line is flushed once the pipe is closed
+        writer.write_all(b"ee").expect("failed to write to pipe");
+        drop(writer);
+        lines.clear();
+        assert!(!pipe.read(&mut lines).expect("failed to read pipe"));
+        assert_eq!(text(&lines), ["three"]);
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let (mut pipe, mut writer) = pipe();
+        let mut lines = Vec::new();
+
+        // Write from another thread since the line exceeds the pipe capacity
+        let writer = thread::spawn(move || writer.write_all(&[b'x'; MAX_LINE_LEN + 1]));
+        while pipe.read(&mut lines).expect("failed to read pipe") {}
+        writer.join().expect("writer panicked").expect("failed to write to pipe");
+        assert_eq!(lines.iter().map(|output| output.line.len()).collect::<Vec<_>>(), [MAX_LINE_LEN, 1]);
+    }
+
+    #[test]
+    fn output_is_captured() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2"]).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = command.spawn().expect("failed to spawn child");
+        let output = OutputEventSource::new();
+        output.capture(&mut child, 7).expect("failed to capture output");
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        reactor.attach(output.clone(), Some, None);
+
+        // Both streams are reported until the pipes are closed
+        let mut lines = Vec::new();
+        while !output.event_fds().is_empty() {
+            lines.extend(reactor.wait().expect("failed to wait").into_iter().flatten());
+        }
+        child.wait().expect("failed to wait for child");
+        lines.sort_by_key(|output| output.line.clone());
+        let streams: Vec<_> = lines.iter().map(|output| (output.stream, output.line.as_str())).collect();
+        assert_eq!(streams, [(Stream::Stderr, "err"), (Stream::Stdout, "out")]);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    error::{ Context, Result },
+    events::EventSource,
+    log
+};
+use std::{
+    io,
+    os::unix::io::{ AsRawFd, FromRawFd, OwnedFd, RawFd },
+    panic::{ self, AssertUnwindSafe },
+    time::Instant
+};
+
+
+/// An event source that has been attached to a reactor
+trait Attachment<T> {
+    /// The file descriptors of the event source
+    fn fds(&self) -> Vec<RawFd>;
+    /// The file descriptors of the event source that wait for writability
+    fn write_fds(&self) -> Vec<RawFd>;
+    /// The deadline of the event source (if any)
+    fn deadline(&self) -> Option<Instant>;
+    /// Processes the pending events and converts them into event messages
+    fn poll(&mut self) -> Result<Vec<T>>;
+}
+
+
+/// An event source together with the conversion into event messages
+struct Mapped<S, F> {
+    /// The event source
+    source: S,
+    /// The conversion into event messages
+    map: F
+}
+impl<S, F, T> Attachment<T> for Mapped<S, F> where S: EventSource, F: FnMut(S::Event) -> T {
+    fn fds(&self) -> Vec<RawFd> {
+        self.source.event_fds()
+    }
+    fn write_fds(&self) -> Vec<RawFd> {
+        self.source.event_write_fds()
+    }
+    fn deadline(&self) -> Option<Instant> {
+        self.source.event_deadline()
+    }
+    fn poll(&mut self) -> Result<Vec<T>> {
+        let events = self.source.event_poll()?;
+        Ok(events.into_iter().map(&mut self.map).collect())
+    }
+}
+
+
+/// An attached event source
+struct Entry<T> {
+    /// The event source
+    source: Box<dyn Attachment<T>>,
+    /// The message that is emitted if the event source fails
+    failure: T,
+    /// The currently registered file descriptors together with their `epoll` events
+    registered: Vec<(RawFd, libc::c_int)>
+}
+impl<T> Entry<T> {
+    /// The file descriptors of the event source together with their `epoll` events
+    fn interests(&self) -> Vec<(RawFd, libc::c_int)> {
+        let read = self.source.fds().into_iter().map(|fd| (fd, libc::EPOLLIN));
+        read.chain(self.source.write_fds().into_iter().map(|fd| (fd, libc::EPOLLOUT))).collect()
+    }
+}
+
+
+/// A reactor that waits for all attached event sources within a single `epoll` instance
+pub struct Reactor<T> {
+    /// The `epoll` instance
+    epoll: OwnedFd,
+    /// The attached event sources (indexed by their `epoll` token)
+    entries: Vec<Option<Entry<T>>>
+}
+impl<T> Reactor<T> {
+    /// Creates a new reactor
+    pub fn new() -> Result<Self> {
+        // SAFETY: `epoll_create1` has no memory-safety preconditions
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll < 0 {
+            return Err(io::Error::last_os_error()).operation("create epoll instance");
+        }
+
+        // SAFETY: `epoll` is a valid file descriptor that is owned by nobody else
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        Ok(Self { epoll, entries: Vec::new() })
+    }
+
+    /// Attaches an event source whose events are converted into messages via `map`; if the event source fails or panics,
+    /// it is detached and `failure` is emitted
+    pub fn attach<S, F>(&mut self, source: S, map: F, failure: T)
+        where S: EventSource + 'static, F: FnMut(S::Event) -> T + 'static, T: 'static
+    {
+        let entry = Entry { source: Box::new(Mapped { source, map }), failure, registered: Vec::new() };
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(slot) => *slot = Some(entry),
+            None => self.entries.push(Some(entry))
+        }
+    }
+
+    /// Waits until at least one event source is ready or has reached its deadline and returns the resulting messages
+    ///
+    /// # Note
+    /// The returned messages may be empty if an event source has been polled without producing any event.
+    pub fn wait(&mut self) -> Result<Vec<T>> {
+        // Deregister the file descriptors that are gone first, since their numbers may have been reused by another
+        // event source
+        let epoll = self.epoll.as_raw_fd();
+        let interests: Vec<_> = self.entries.iter().map(|entry| entry.as_ref().map(Entry::interests)).collect();
+        for (entry, interests) in self.entries.iter().zip(&interests) {
+            let (Some(entry), Some(interests)) = (entry, interests) else {
+                continue;
+            };
+            for (fd, _) in &entry.registered {
+                if !interests.iter().any(|(current, _)| current == fd) {
+                    Self::deregister(epoll, *fd);
+                }
+            }
+        }
+
+        // Register the new or changed file descriptors and compute the earliest deadline
+        let mut deadline: Option<Instant> = None;
+        for (token, (entry, interests)) in self.entries.iter_mut().zip(interests).enumerate() {
+            let (Some(entry), Some(interests)) = (entry, interests) else {
+                continue;
+            };
+            for &(fd, events) in &interests {
+                if !entry.registered.contains(&(fd, events)) {
+                    Self::register(epoll, fd, token, events)?;
+                }
+            }
+            entry.registered = interests;
+            if let Some(next) = entry.source.deadline() {
+                deadline = Some(deadline.map_or(next, |deadline| deadline.min(next)));
+            }
+        }
+
+        // Wait for the file descriptors or the deadline (rounded up to the next millisecond)
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+            },
+            None => -1
+        };
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 16];
+        // SAFETY: `events` is a valid buffer for `events.len()` entries
+        let ready = unsafe { libc::epoll_wait(self.epoll.as_raw_fd(), events.as_mut_ptr(), events.len() as i32, timeout) };
+        let ready = match ready {
+            ready if ready >= 0 => &events[..ready as usize],
+            _ => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => &events[..0],
+                e => return Err(e).operation("wait for events")
+            }
+        };
+
+        // Collect the event sources that are ready or have reached their deadline
+        let now = Instant::now();
+        let mut tokens: Vec<_> = ready.iter().map(|event| event.u64 as usize).collect();
+        for (token, entry) in self.entries.iter().enumerate() {
+            let expired = entry.as_ref().and_then(|entry| entry.source.deadline()).is_some_and(|deadline| deadline <= now);
+            if expired {
+                tokens.push(token);
+            }
+        }
+        tokens.sort_unstable();
+        tokens.dedup();
+
+        // Poll the event sources and detach failed event sources
+        let mut messages = Vec::new();
+        for token in tokens {
+            let Some(Some(entry)) = self.entries.get_mut(token) else {
+                continue;
+            };
+            match panic::catch_unwind(AssertUnwindSafe(|| entry.source.poll())) {
+                Ok(Ok(events)) => messages.extend(events),
+                Ok(Err(e)) => {
+                    log::error("source_failed", format!("Event source failed: {}", e)).error(e).emit();
+                    messages.extend(self.detach(token));
+                },
+                Err(_) => messages.extend(self.detach(token))
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Detaches the event source `token` and returns its failure message
+    fn detach(&mut self, token: usize) -> Option<T> {
+        let entry = self.entries.get_mut(token)?.take()?;
+        for (fd, _) in entry.registered {
+            Self::deregister(self.epoll.as_raw_fd(), fd);
+        }
+        Some(entry.failure)
+    }
+
+    /// Registers `fd` for `events` (`EPOLLIN` or `EPOLLOUT`) with `epoll`, or updates the registration if it is already
+    /// registered
+    fn register(epoll: RawFd, fd: RawFd, token: usize, events: libc::c_int) -> Result {
+        let mut event = libc::epoll_event { events: events as u32, u64: token as u64 };
+        // SAFETY: `event` is a valid event struct; invalid file descriptors are reported as error
+        let mut result = unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, fd, &mut event) };
+        if result != 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EEXIST) {
+            // SAFETY: see above
+            result = unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_MOD, fd, &mut event) };
+        }
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()).operation("register event source")
+        }
+    }
+
+    /// Removes `fd` from `epoll`; closed file descriptors have already been removed, so errors are irrelevant here
+    fn deregister(epoll: RawFd, fd: RawFd) {
+        // SAFETY: removing a file descriptor has no memory-safety preconditions
+        unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ error::Error, events::timer::TimerEventSource };
+    use std::{ cell::RefCell, fs::File, io::{ Read, Write }, rc::Rc, time::Duration };
+
+    /// Creates a non-blocking pipe and returns its reader and writer
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` is a valid buffer for two file descriptors
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) }, 0);
+        // SAFETY: the file descriptors have just been created and are not owned by anything else
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    /// An event source that drains the pipe readers (shared between all clones) and reports each poll
+    #[derive(Clone, Default)]
+    struct Pipes {
+        /// The pipe readers
+        readers: Rc<RefCell<Vec<File>>>
+    }
+    impl EventSource for Pipes {
+        type Event = ();
+
+        fn event_fds(&self) -> Vec<RawFd> {
+            self.readers.borrow().iter().map(AsRawFd::as_raw_fd).collect()
+        }
+        fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+            let mut buf = [0; 64];
+            for mut reader in self.readers.borrow().iter() {
+                while let Ok(1..) = reader.read(&mut buf) {}
+            }
+            Ok(vec![()])
+        }
+    }
+
+    /// An event source that is due immediately and fails or panics once it is polled
+    struct Faulty {
+        /// The point in time at which the event source is due
+        deadline: Instant,
+        /// Whether the event source panics instead of returning an error
+        panics: bool
+    }
+    impl EventSource for Faulty {
+        type Event = ();
+
+        fn event_fds(&self) -> Vec<RawFd> {
+            Vec::new()
+        }
+        fn event_deadline(&self) -> Option<Instant> {
+            Some(self.deadline)
+        }
+        fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+            match self.panics {
+                true => panic!("faulty event source"),
+                false => Err(Error::Poisoned("faulty event source"))
+            }
+        }
+    }
+
+    #[test]
+    fn failed_sources_are_detached() {
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        reactor.attach(Faulty { deadline: Instant::now(), panics: false }, |_| "event", "failed");
+        reactor.attach(Faulty { deadline: Instant::now(), panics: true }, |_| "event", "panicked");
+        assert_eq!(reactor.wait().expect("failed to wait"), ["failed", "panicked"]);
+
+        // The detached event sources are not polled again
+        let timer = TimerEventSource::new();
+        timer.arm(Instant::now());
+        reactor.attach(timer, |_| "timer", "timer failed");
+        assert_eq!(reactor.wait().expect("failed to wait"), ["timer"]);
+    }
+
+    #[test]
+    fn registrations_follow_the_file_descriptors() {
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        let (pipes, timer) = (Pipes::default(), TimerEventSource::new());
+        reactor.attach(pipes.clone(), |_| "pipes", "pipes failed");
+        reactor.attach(timer.clone(), |_| "timer", "timer failed");
+
+        // New file descriptors are registered
+        let (first, mut first_writer) = pipe();
+        pipes.readers.borrow_mut().push(first);
+        first_writer.write_all(b"first").expect("failed to write to pipe");
+        assert_eq!(reactor.wait().expect("failed to wait"), ["pipes"]);
+
+        // Replaced file descriptors are deregistered even if they are still open
+        let (second, mut second_writer) = pipe();
+        let first = pipes.readers.replace(vec![second]);
+        first_writer.write_all(b"first").expect("failed to write to pipe");
+        timer.arm(Instant::now());
+        assert_eq!(reactor.wait().expect("failed to wait"), ["timer"]);
+        second_writer.write_all(b"second").expect("failed to write to pipe");
+        assert_eq!(reactor.wait().expect("failed to wait"), ["pipes"]);
+        drop(first);
+    }
+
+    #[test]
+    fn deadlines_are_ordered() {
+        let mut reactor = Reactor::new().expect("failed to create reactor");
+        let (early, late) = (TimerEventSource::new(), TimerEventSource::new());
+        reactor.attach(late.clone(), |_| "late", "late failed");
+        reactor.attach(early.clone(), |_| "early", "early failed");
+        let now = Instant::now();
+        late.arm(now + Duration::from_millis(40));
+        early.arm(now + Duration::from_millis(20));
+        assert_eq!(reactor.wait().expect("failed to wait"), ["early"]);
+        assert_eq!(reactor.wait().expect("failed to wait"), ["late"]);
+        assert!(now.elapsed().as_millis() >= 40);
+    }
+}
@@ -1,85 +1,80 @@
-use crate::events::EventSource;
-use signal_hook::consts::{ SIGTERM, SIGQUIT, SIGINT };
+use crate::{
+    error::{ Context, Result },
+    events::EventSource
+};
 use std::{
-    thread, time::Duration,
-    sync::{
-        Arc, mpsc::Sender,
-        atomic::{ AtomicBool, Ordering }
-    }
+    io, mem,
+    os::unix::io::{ AsRawFd, FromRawFd, OwnedFd, RawFd }
 };
 
 
-/// An asynchronous signal event source
-struct SignalEventSourceImpl<T> {
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>,
-    /// The event message
-    message: T,
-    /// The event channel
-    channel: Sender<T>
+/// A unix signal event source that traps a set of signals
+///
+/// # Note
+/// The signals are blocked for the calling thread and all threads it spawns afterwards and are received via `signalfd`.
+pub struct SignalEventSource {
+    /// The signal file descriptor
+    fd: OwnedFd
 }
-impl<T> SignalEventSourceImpl<T> where T: Clone + Send + 'static {
-    /// Creates a new asynchronous signal event source
-    pub fn start(active: Arc<AtomicBool>, message: T, channel: Sender<T>) {
-        let this = Self { active, message, channel };
-        thread::spawn(|| this.runloop());
-    }
-
-    /// The runloop
-    fn runloop(self) {
-        // Register the signals
-        let flag = Arc::new(AtomicBool::new(false));
-        signal_hook::flag::register(SIGTERM, flag.clone()).expect("Failed to register unix signal handler");
-        signal_hook::flag::register(SIGQUIT, flag.clone()).expect("Failed to register unix signal handler");
-        signal_hook::flag::register(SIGINT, flag.clone()).expect("Failed to register unix signal handler");
-
-        // Loop as long as the event source is valid
-        'runloop: while self.active.load(Ordering::Relaxed) {
-            // The signal has occurred
-            if flag.swap(false, Ordering::Relaxed) {
-                // Send the event message
-                let message = self.message.clone();
-                if let Err(_) = self.channel.send(message) {
-                    break 'runloop;
-                }
-            }
+impl SignalEventSource {
+    /// Creates a new signal event source that traps `signals`
+    pub fn new(signals: &[libc::c_int]) -> Result<Self> {
+        // Create the signal set
+        // SAFETY: `sigset_t` is a plain C struct that is initialized by `sigemptyset`
+        let mut set: libc::sigset_t = unsafe { mem::zeroed() };
+        // SAFETY: `set` is a valid signal set
+        unsafe { libc::sigemptyset(&mut set) };
+        for &signal in signals {
+            // SAFETY: `set` is a valid signal set and `signal` is a valid signal number
+            unsafe { libc::sigaddset(&mut set, signal) };
+        }
 
-            // Sleep some time
-            const SPINLOOP_INTERVAL: Duration = Duration::from_millis(100);
-            thread::sleep(SPINLOOP_INTERVAL);
+        // Block the signals so that they are only delivered via the signal file descriptor
+        // SAFETY: `set` is a valid signal set
+        let result = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result)).operation("block unix signals");
         }
-    }
-}
 
+        // Create the signal file descriptor
+        // SAFETY: `set` is a valid signal set
+        let fd = unsafe { libc::signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).operation("create signal file descriptor");
+        }
 
-/// A unix signal event source that traps all termination signals (SIGINT, SIGTERM, SIGQUIT, etc.)
-pub struct SignalEventSource {
-    /// A flag that signalizes that the event source should be active
-    active: Arc<AtomicBool>
-}
-impl SignalEventSource {
-    /// Creates a new signal event source
-    pub fn new() -> Self {
-        let active = Arc::new(AtomicBool::new(true));
-        Self { active }
+        // SAFETY: `fd` is a valid file descriptor that is owned by nobody else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Self { fd })
     }
 }
-impl<T> EventSource<T> for SignalEventSource where T: Clone + Send + 'static {
-    fn event_attach(&mut self, message: T, channel: Sender<T>) {
-        self.active.store(true, Ordering::SeqCst);
-        let active = self.active.clone();
-        SignalEventSourceImpl::start(active, message, channel);
-    }
-    fn event_cancel(&mut self) {
-        self.active.store(false, Ordering::SeqCst);
-    }
-    fn event_detach(&mut self) {
-        self.active.store(true, Ordering::SeqCst);
-        self.active = Arc::new(AtomicBool::new(true));
+impl EventSource for SignalEventSource {
+    /// The signal number
+    type Event = libc::c_int;
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        vec![self.fd.as_raw_fd()]
     }
-}
-impl Drop for SignalEventSource {
-    fn drop(&mut self) {
-        self.active.store(false, Ordering::Relaxed);
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        let mut signals = Vec::new();
+        loop {
+            // Read the next signal
+            // SAFETY: `signalfd_siginfo` is a plain C struct
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of::<libc::signalfd_siginfo>();
+            // SAFETY: `info` is a valid buffer of `size` bytes
+            let read = unsafe { libc::read(self.fd.as_raw_fd(), &mut info as *mut _ as *mut libc::c_void, size) };
+
+            // Collect the signal or stop if there are no more pending signals
+            match read {
+                read if read as usize == size => signals.push(info.ssi_signo as libc::c_int),
+                read if read >= 0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)).operation("read signal"),
+                _ => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::WouldBlock => return Ok(signals),
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e).operation("read signal")
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,125 @@
+use crate::{
+    error::{ Context, Result },
+    events::EventSource,
+    syslog::{ Message, SocketMode, SocketOwner }
+};
+use std::{
+    fs, io,
+    net::{ SocketAddr, UdpSocket },
+    os::unix::{
+        fs::{ self as unix_fs, FileTypeExt, PermissionsExt },
+        io::{ AsRawFd, RawFd },
+        net::UnixDatagram
+    },
+    path::{ Path, PathBuf },
+    rc::Rc
+};
+
+
+/// The maximum size of a syslog datagram
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+/// The maximum amount of datagrams that are received at once so that a busy sender cannot starve the other event sources
+const MAX_DATAGRAMS_PER_POLL: usize = 256;
+
+
+/// The listening sockets
+#[derive(Debug)]
+struct Sockets {
+    /// The Unix datagram socket together with its path (if any)
+    unix: Option<(UnixDatagram, PathBuf)>,
+    /// The UDP socket (if any)
+    udp: Option<UdpSocket>
+}
+
+
+/// A syslog event source that receives messages via a Unix datagram socket and/or a UDP socket
+///
+/// # Note
+/// All clones share the same sockets, so the event source can be reattached without rebinding the sockets.
+#[derive(Debug, Clone)]
+pub struct SyslogEventSource {
+    /// The listening sockets
+    sockets: Rc<Sockets>
+}
+impl SyslogEventSource {
+    /// Binds a Unix datagram socket at `path` (replacing a stale socket) with the permissions `mode` and the `owner` (if
+    /// any), and/or a UDP socket at `udp`
+    pub fn bind(path: Option<&Path>, mode: SocketMode, owner: Option<SocketOwner>, udp: Option<SocketAddr>)
+        -> Result<Self>
+    {
+        // Bind the Unix datagram socket
+        let unix = match path {
+            Some(path) => {
+                if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    fs::remove_file(path).context("remove stale syslog socket", path)?;
+                }
+                let socket = UnixDatagram::bind(path).context("bind syslog socket", path)?;
+                if let Some(SocketOwner { uid, gid }) = owner {
+                    unix_fs::chown(path, uid, gid).context("change owner of syslog socket", path)?;
+                }
+                let permissions = fs::Permissions::from_mode(mode.0);
+                fs::set_permissions(path, permissions).context("change mode of syslog socket", path)?;
+                socket.set_nonblocking(true).context("configure syslog socket", path)?;
+                Some((socket, path.to_path_buf()))
+            },
+            None => None
+        };
+
+        // Bind the UDP socket
+        let udp = match udp {
+            Some(address) => {
+                let socket = UdpSocket::bind(address).operation("bind syslog UDP socket")?;
+                socket.set_nonblocking(true).operation("configure syslog UDP socket")?;
+                Some(socket)
+            },
+            None => None
+        };
+        Ok(Self { sockets: Rc::new(Sockets { unix, udp }) })
+    }
+
+    /// The address HAProxy should log to (the Unix socket path if any, or the bound UDP address)
+    pub fn address(&self) -> Option<String> {
+        if let Some((_, path)) = &self.sockets.unix {
+            return Some(path.display().to_string());
+        }
+        let address = self.sockets.udp.as_ref()?.local_addr().ok()?;
+        Some(address.to_string())
+    }
+
+    /// Receives up to `limit` pending datagrams via `recv` and parses them into `messages`
+    fn receive<F>(mut recv: F, limit: usize, messages: &mut Vec<Message>) -> io::Result<()>
+        where F: FnMut(&mut [u8]) -> io::Result<usize>
+    {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        while messages.len() < limit {
+            match recv(&mut buffer) {
+                Ok(size) => messages.push(Message::parse(&buffer[..size])),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(())
+    }
+}
+impl EventSource for SyslogEventSource {
+    /// A syslog message
+    type Event = Message;
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        let unix = self.sockets.unix.iter().map(|(socket, _)| socket.as_raw_fd());
+        unix.chain(self.sockets.udp.iter().map(|socket| socket.as_raw_fd())).collect()
+    }
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        let mut messages = Vec::new();
+        if let Some((socket, path)) = &self.sockets.unix {
+            Self::receive(|buffer| socket.recv(buffer), MAX_DATAGRAMS_PER_POLL, &mut messages)
+                .context("receive syslog message", path)?;
+        }
+        if let Some(socket) = &self.sockets.udp {
+            let limit = messages.len() + MAX_DATAGRAMS_PER_POLL;
+            Self::receive(|buffer| socket.recv(buffer), limit, &mut messages).operation("receive syslog message")?;
+        }
+        Ok(messages)
+    }
+}
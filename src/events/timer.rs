@@ -0,0 +1,53 @@
+use crate::{ error::Result, events::EventSource };
+use std::{
+    cell::Cell,
+    os::unix::io::RawFd,
+    rc::Rc,
+    time::Instant
+};
+
+
+/// A one-shot timer event source
+///
+/// # Note
+/// All clones share the same timer, so a clone can be attached to the reactor while another clone is used to arm it.
+#[derive(Debug, Clone, Default)]
+pub struct TimerEventSource {
+    /// The point in time at which the timer fires (or `None` if the timer is not armed)
+    deadline: Rc<Cell<Option<Instant>>>
+}
+impl TimerEventSource {
+    /// Creates a new disarmed timer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Arms the timer to fire once at `deadline`
+    pub fn arm(&self, deadline: Instant) {
+        self.deadline.set(Some(deadline));
+    }
+    /// Disarms the timer
+    pub fn disarm(&self) {
+        self.deadline.set(None);
+    }
+}
+impl EventSource for TimerEventSource {
+    /// The expiration of the timer
+    type Event = ();
+
+    fn event_fds(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+    fn event_deadline(&self) -> Option<Instant> {
+        self.deadline.get()
+    }
+    fn event_poll(&mut self) -> Result<Vec<Self::Event>> {
+        match self.deadline.get() {
+            Some(deadline) if deadline <= Instant::now() => {
+                self.deadline.set(None);
+                Ok(vec![()])
+            },
+            _ => Ok(Vec::new())
+        }
+    }
+}
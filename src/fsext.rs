@@ -1,6 +1,7 @@
 use std::{
-    fs,
-    io::{ Result, ErrorKind },
+    fs::{ self, File },
+    io::{ Error, Read, Result, ErrorKind },
+    os::unix::io::AsRawFd,
     path::{ Path, PathBuf }
 };
 
@@ -50,6 +51,17 @@ pub fn list_files<D>(directory: D) -> Result<Vec<PathBuf>> where D: AsRef<Path>
 }
 
 
+/// Reads the remaining contents of `file` or fails with `ErrorKind::FileTooLarge` if it is larger than `limit` bytes
+pub fn read_limited(file: File, limit: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.take(limit.saturating_add(1)).read_to_end(&mut data)?;
+    match data.len() as u64 {
+        len if len > limit => Err(Error::new(ErrorKind::FileTooLarge, format!("file exceeds {} bytes", limit))),
+        _ => Ok(data)
+    }
+}
+
+
 /// Writes a file atomically
 pub fn write_atomic<D, F>(data: D, path: F) -> Result<()> where D: AsRef<[u8]>, F: AsRef<Path> {
     // Create the path and temp path
@@ -64,11 +76,29 @@ pub fn write_atomic<D, F>(data: D, path: F) -> Result<()> where D: AsRef<[u8]>,
     
     // Write the file
     fs::write(&temp_path, data)?;
-    fs::rename(&temp_path, &path)?;
+    fs::rename(&temp_path, path)?;
     Ok(())
 }
 
 
+/// Tries to acquire a shared advisory lock (`flock`) on `file` without blocking
+/// 
+/// Returns `false` if another process holds an exclusive lock. The lock is released if `file` is closed. If the file system
+/// does not support locks (e.g. some NFS or FUSE mounts), the file is treated as not locked.
+pub fn try_lock_shared(file: &File) -> Result<bool> {
+    // SAFETY: `flock` only operates on the valid file descriptor owned by `file`
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) };
+    match result {
+        0 => Ok(true),
+        _ => match Error::last_os_error() {
+            e if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            e if matches!(e.raw_os_error(), Some(libc::ENOLCK | libc::EOPNOTSUPP)) => Ok(true),
+            e => Err(e)
+        }
+    }
+}
+
+
 /// Converts a path into a sequence of raw bytes
 /// 
 /// # Note
@@ -95,10 +125,20 @@ pub fn path_bytes<P>(path: P) -> Vec<u8> where P: AsRef<Path> {
         return path_bytes;
     }
     
-    // Fallback to generic but fallible method
+    // Fallback to generic but lossy method
     #[allow(unused)]
     {
-        let path_str = path.as_ref().to_str().expect("Path contains non-UTF-8 sequences");
-        return path_str.as_bytes().to_vec();
+        let path_str = path.as_ref().to_string_lossy();
+        path_str.as_bytes().to_vec()
     }
 }
+
+
+/// Creates an empty directory for the test `name` within the temporary directory
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("autoconfd-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("failed to create test directory");
+    directory
+}
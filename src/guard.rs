@@ -0,0 +1,122 @@
+use std::{ collections::VecDeque, time::{ Duration, Instant } };
+
+
+/// The marker file that confirms a change that has been blocked by the [`DeletionGuard`]
+pub const CONFIRM_MARKER: &str = ".autoconfd-confirm";
+/// The window within which applied amounts of fragments count towards the [`Reference`]
+const REFERENCE_WINDOW: Duration = Duration::from_secs(3600);
+
+
+/// A guard against sudden mass-deletions within the inbox (e.g. if the inbox volume failed to mount)
+#[derive(Debug, Clone, Copy)]
+pub struct DeletionGuard {
+    /// The maximum percentage of fragments that may be removed at once
+    pub max_removal_percent: usize,
+    /// The minimum amount of fragments
+    pub min_fragments: usize
+}
+impl DeletionGuard {
+    /// Checks whether the amount of fragments may change from the reference amount `previous` (if known) to `current`
+    pub fn check(&self, previous: Option<usize>, current: usize) -> Result<(), String> {
+        // Check the minimum amount of fragments
+        if current < self.min_fragments {
+            return Err(format!("only {} fragments left (minimum is {})", current, self.min_fragments));
+        }
+
+        // Check the removal percentage
+        let previous = previous.unwrap_or_default();
+        let removed = previous.saturating_sub(current);
+        if removed * 100 > previous * self.max_removal_percent {
+            let percent = removed * 100 / previous;
+            return Err(format!("{} of {} fragments ({}%) would be removed (maximum is {}%)", removed, previous, percent,
+                self.max_removal_percent));
+        }
+        Ok(())
+    }
+}
+impl Default for DeletionGuard {
+    fn default() -> Self {
+        Self { max_removal_percent: 100, min_fragments: 0 }
+    }
+}
+
+
+/// The reference amount of fragments for the [`DeletionGuard`]
+///
+/// The reference is the highest amount of fragments that has been applied within the last hour (or the last applied amount
+/// if that is older), so removing fragments one after another cannot bypass the guard, while fragments can still be
+/// decommissioned gradually over a longer period of time.
+#[derive(Debug, Default)]
+pub struct Reference {
+    /// The applied amounts of fragments together with the time they have been applied at (oldest first)
+    applied: VecDeque<(Instant, usize)>
+}
+impl Reference {
+    /// The reference amount at `now` (if any fragments have been applied yet)
+    pub fn get(&self, now: Instant) -> Option<usize> {
+        let recent = self.applied.iter().filter(|(time, _)| now.saturating_duration_since(*time) <= REFERENCE_WINDOW);
+        recent.chain(self.applied.back()).map(|(_, count)| *count).max()
+    }
+
+    /// Records that `count` fragments have been applied at `now`; a confirmed change replaces all previous amounts
+    pub fn applied(&mut self, count: usize, confirmed: bool, now: Instant) {
+        if confirmed {
+            self.applied.clear();
+        }
+        self.applied.push_back((now, count));
+        while self.applied.front().is_some_and(|(time, _)| now.saturating_duration_since(*time) > REFERENCE_WINDOW) {
+            self.applied.pop_front();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_removals() {
+        let guard = DeletionGuard { max_removal_percent: 50, min_fragments: 2 };
+        assert_eq!(guard.check(None, 2), Ok(()));
+        assert_eq!(guard.check(Some(10), 5), Ok(()));
+        assert_eq!(guard.check(Some(10), 20), Ok(()));
+        assert_eq!(guard.check(Some(10), 4), Err("6 of 10 fragments (60%) would be removed (maximum is 50%)".to_string()));
+        assert_eq!(guard.check(Some(10), 1), Err("only 1 fragments left (minimum is 2)".to_string()));
+        assert_eq!(guard.check(None, 0), Err("only 0 fragments left (minimum is 2)".to_string()));
+
+        // The default guard allows everything
+        assert_eq!(DeletionGuard::default().check(Some(10), 0), Ok(()));
+    }
+
+    #[test]
+    fn reference_within_window() {
+        let (mut reference, now) = (Reference::default(), Instant::now());
+        assert_eq!(reference.get(now), None);
+
+        // Removing fragments one after another is measured against the highest amount within the window
+        reference.applied(10, false, now);
+        reference.applied(8, false, now + Duration::from_secs(60));
+        reference.applied(6, false, now + Duration::from_secs(120));
+        assert_eq!(reference.get(now + Duration::from_secs(180)), Some(10));
+
+        // A confirmed change resets the reference
+        reference.applied(3, true, now + Duration::from_secs(240));
+        assert_eq!(reference.get(now + Duration::from_secs(300)), Some(3));
+    }
+
+    #[test]
+    fn reference_decays() {
+        let (mut reference, now) = (Reference::default(), Instant::now());
+        reference.applied(10, false, now);
+        reference.applied(8, false, now + REFERENCE_WINDOW / 2);
+
+        // Old amounts expire, but the last applied amount is always kept
+        assert_eq!(reference.get(now + REFERENCE_WINDOW), Some(10));
+        assert_eq!(reference.get(now + REFERENCE_WINDOW + Duration::from_secs(1)), Some(8));
+        assert_eq!(reference.get(now + REFERENCE_WINDOW * 2), Some(8));
+        reference.applied(6, false, now + REFERENCE_WINDOW * 3);
+        assert_eq!(reference.applied.len(), 1);
+        assert_eq!(reference.get(now + REFERENCE_WINDOW * 3), Some(6));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{ fsext::FilePattern, log };
+use std::{ fs, io::ErrorKind, path::Path };
+
+
+/// The name of the per-directory ignore file
+pub const IGNORE_FILE: &str = ".autoconfdignore";
+/// The built-in default ignore rules (dotfiles, editor swap/backup files and in-progress temp files)
+const DEFAULT_RULES: &str = "\
+.*
+*~
+\\#*#
+*.swp
+*.swo
+*.swx
+*.tmp
+*.bak
+4913
+";
+
+
+/// A single glob token
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A literal byte
+    Literal(u8),
+    /// Matches any single byte (`?`)
+    Any,
+    /// Matches any sequence of bytes (`*`)
+    Star,
+    /// Matches a single byte within (or if negated: not within) the given inclusive ranges (`[...]`)
+    Class { negated: bool, ranges: Vec<(u8, u8)> }
+}
+
+
+/// A single gitignore-style rule
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Whether the rule re-includes matching files (`!pattern`)
+    negated: bool,
+    /// The glob tokens
+    glob: Vec<Token>
+}
+impl Rule {
+    /// Parses a single line of a gitignore-style file
+    ///
+    /// # Note
+    /// Since the inbox is scanned non-recursively, directory-only rules (`foo/`) and rules that refer to nested paths
+    /// (`foo/bar`) can never match and are skipped.
+    fn parse(line: &[u8]) -> Option<Self> {
+        // Trim the line ending and unescaped trailing whitespace
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        while let [rest @ .., b' ' | b'\t'] = line {
+            match rest.last() {
+                Some(b'\\') => break,
+                _ => line = rest
+            }
+        }
+
+        // Skip empty lines and comments
+        if line.is_empty() || line.starts_with(b"#") {
+            return None;
+        }
+
+        // Parse the negation
+        let (negated, mut line) = match line.strip_prefix(b"!") {
+            Some(line) => (true, line),
+            None => (false, line)
+        };
+
+        // Normalize the anchoring; we only deal with top-level files
+        if line.ends_with(b"/") {
+            return None;
+        }
+        line = line.strip_prefix(b"**/").or_else(|| line.strip_prefix(b"/")).unwrap_or(line);
+        if line.is_empty() || line.contains(&b'/') {
+            return None;
+        }
+
+        let glob = Self::tokenize(line);
+        Some(Self { negated, glob })
+    }
+
+    /// Tokenizes a glob pattern
+    fn tokenize(mut pattern: &[u8]) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        while let Some((&byte, rest)) = pattern.split_first() {
+            pattern = rest;
+            match byte {
+                b'\\' => match pattern.split_first() {
+                    Some((&escaped, rest)) => {
+                        tokens.push(Token::Literal(escaped));
+                        pattern = rest;
+                    },
+                    None => tokens.push(Token::Literal(b'\\'))
+                },
+                b'*' if tokens.last() == Some(&Token::Star) => continue,
+                b'*' => tokens.push(Token::Star),
+                b'?' => tokens.push(Token::Any),
+                b'[' => match Self::tokenize_class(pattern) {
+                    Some((class, rest)) => {
+                        tokens.push(class);
+                        pattern = rest;
+                    },
+                    None => tokens.push(Token::Literal(b'['))
+                },
+                byte => tokens.push(Token::Literal(byte))
+            }
+        }
+        tokens
+    }
+
+    /// Tokenizes a character class (the part after the opening `[`) and returns the class and the remaining pattern
+    fn tokenize_class(mut pattern: &[u8]) -> Option<(Token, &[u8])> {
+        // Parse the negation
+        let negated = matches!(pattern.first(), Some(b'!' | b'^'));
+        if negated {
+            pattern = &pattern[1..];
+        }
+
+        // Collect the ranges; a leading `]` is treated as literal
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let (&start, rest) = pattern.split_first()?;
+            pattern = rest;
+            let start = match start {
+                b']' if !first => return Some((Token::Class { negated, ranges }, pattern)),
+                b'\\' => {
+                    let (&escaped, rest) = pattern.split_first()?;
+                    pattern = rest;
+                    escaped
+                },
+                start => start
+            };
+            first = false;
+
+            // Check for a range
+            match pattern {
+                [b'-', end, rest @ ..] if *end != b']' => {
+                    ranges.push((start, *end));
+                    pattern = rest;
+                },
+                _ => ranges.push((start, start))
+            }
+        }
+    }
+
+    /// Tests whether `name` matches the rule's glob
+    fn matches(&self, name: &[u8]) -> bool {
+        // Iterative glob matching with single-star backtracking
+        let (mut glob_pos, mut name_pos) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+        while name_pos < name.len() {
+            let matched = match self.glob.get(glob_pos) {
+                Some(Token::Star) => {
+                    backtrack = Some((glob_pos, name_pos));
+                    glob_pos += 1;
+                    continue;
+                },
+                Some(Token::Literal(literal)) => *literal == name[name_pos],
+                Some(Token::Any) => true,
+                Some(Token::Class { negated, ranges }) => {
+                    let byte = name[name_pos];
+                    let in_class = ranges.iter().any(|(start, end)| (*start..=*end).contains(&byte));
+                    in_class != *negated
+                },
+                None => false
+            };
+
+            // Advance or backtrack to the last star
+            match (matched, backtrack) {
+                (true, _) => (glob_pos, name_pos) = (glob_pos + 1, name_pos + 1),
+                (false, Some((star_pos, star_name_pos))) => {
+                    backtrack = Some((star_pos, star_name_pos + 1));
+                    (glob_pos, name_pos) = (star_pos + 1, star_name_pos + 1);
+                },
+                (false, None) => return false
+            }
+        }
+
+        // Skip trailing stars
+        self.glob[glob_pos..].iter().all(|token| *token == Token::Star)
+    }
+}
+
+
+/// A set of gitignore-style ignore rules
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    /// The rules in order of precedence (i.e. the last matching rule wins)
+    rules: Vec<Rule>
+}
+impl IgnoreRules {
+    /// Creates the built-in default ignore rules
+    pub fn new() -> Self {
+        let mut this = Self { rules: Vec::new() };
+        this.extend(DEFAULT_RULES);
+        this
+    }
+    /// Loads the built-in default ignore rules and extends them with the rules from the ignore file within `directory`
+    /// if it exists
+    pub fn load<D>(directory: D) -> Self where D: AsRef<Path> {
+        let mut this = Self::new();
+        match fs::read(directory.as_ref().join(IGNORE_FILE)) {
+            Ok(rules) => this.extend(rules),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => {
+                let message = format!("Failed to read {}: {}; using default ignore rules", IGNORE_FILE, e);
+                log::warn("ignore_rules", message).error(e).emit();
+            }
+        }
+        this
+    }
+
+    /// Appends the rules from a gitignore-style file
+    pub fn extend<R>(&mut self, rules: R) where R: AsRef<[u8]> {
+        let rules = rules.as_ref().split(|b| *b == b'\n').filter_map(Rule::parse);
+        self.rules.extend(rules);
+    }
+}
+impl FilePattern for IgnoreRules {
+    /// Tests whether a file name is ignored
+    fn matches<D>(&self, data: D) -> bool where D: AsRef<[u8]> {
+        let name = data.as_ref();
+        self.rules.iter().rev().find(|rule| rule.matches(name))
+            .map(|rule| !rule.negated).unwrap_or(false)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests whether `name` matches the single rule `line`
+    fn matches(line: &str, name: &str) -> bool {
+        Rule::parse(line.as_bytes()).expect("invalid rule").matches(name.as_bytes())
+    }
+
+    #[test]
+    fn class_ranges() {
+        assert!(matches("[a-c]x.cfg", "bx.cfg"));
+        assert!(!matches("[a-c]x.cfg", "dx.cfg"));
+        assert!(matches("v[0-9][0-9].cfg", "v42.cfg"));
+        assert!(!matches("v[0-9][0-9].cfg", "v4a.cfg"));
+        assert!(matches("[a-cx].cfg", "x.cfg"));
+        // A trailing or leading dash is literal
+        assert!(matches("[a-].cfg", "-.cfg"));
+        assert!(matches("[-z].cfg", "-.cfg"));
+        // A leading `]` is literal
+        assert!(matches("[]a].cfg", "].cfg"));
+        assert!(matches("[\\]].cfg", "].cfg"));
+    }
+
+    #[test]
+    fn negated_classes() {
+        assert!(matches("[!0-9]*", "a.cfg"));
+        assert!(!matches("[!0-9]*", "1.cfg"));
+        assert!(matches("[^0-9]*", "a.cfg"));
+        assert!(!matches("[^0-9]*", "1.cfg"));
+        // An unterminated class is a literal `[`
+        assert!(matches("[a", "[a"));
+        assert!(!matches("[a", "a"));
+    }
+
+    #[test]
+    fn tokenize_class() {
+        let (class, rest) = Rule::tokenize_class(b"!a-c]x").expect("invalid class");
+        assert_eq!(class, Token::Class { negated: true, ranges: vec![(b'a', b'c')] });
+        assert_eq!(rest, b"x");
+        assert_eq!(Rule::tokenize_class(b"a-c"), None);
+    }
+
+    #[test]
+    fn negated_rules() {
+        let mut rules = IgnoreRules { rules: Vec::new() };
+        rules.extend("*.cfg\n!keep-*.cfg\nkeep-tmp.cfg\n");
+        assert!(rules.matches("drop.cfg"));
+        assert!(!rules.matches("keep-me.cfg"));
+        assert!(rules.matches("keep-tmp.cfg"));
+        assert!(!rules.matches("other.txt"));
+    }
+
+    #[test]
+    fn default_rules_can_be_negated() {
+        let mut rules = IgnoreRules::new();
+        assert!(rules.matches(".hidden.cfg"));
+        assert!(rules.matches("base.cfg.swp"));
+        rules.extend("!.hidden.cfg\n");
+        assert!(!rules.matches(".hidden.cfg"));
+        assert!(rules.matches(".other.cfg"));
+    }
+
+    #[test]
+    fn skipped_lines() {
+        for line in ["", "# comment", "dir/", "nested/file.cfg", "!", "   "] {
+            assert!(Rule::parse(line.as_bytes()).is_none(), "{:?} should be skipped", line);
+        }
+        assert!(matches("\\#literal", "#literal"));
+        assert!(matches("**/base.cfg", "base.cfg"));
+        assert!(matches("/base.cfg", "base.cfg"));
+    }
+}
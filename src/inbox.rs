@@ -0,0 +1,550 @@
+use crate::{
+    admission::Admission,
+    config,
+    error::{ Context, Error, Result },
+    fsext::{ self, FilePattern },
+    ignore::IgnoreRules,
+    limits::{ LimitAction, Limits },
+    log,
+    metrics::Metrics,
+    policy::Policy,
+    signature::{ TrustedKeys, SIGNATURE_EXT },
+    stability::StabilityTracker,
+    symlink::SymlinkPolicy,
+    transaction::TransactionMode
+};
+use std::{
+    fs,
+    collections::{ HashMap, HashSet },
+    io::ErrorKind,
+    ffi::OsString,
+    os::unix::ffi::OsStrExt,
+    path::{ Component, Path, PathBuf },
+    sync::{ Arc, Mutex, PoisonError },
+    time::{ Duration, Instant }
+};
+
+
+/// The symlink that points to the current snapshot of a Kubernetes projected volume
+const PROJECTED_DATA: &str = "..data";
+
+
+/// A config fragment
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    /// The path to the fragment
+    pub path: PathBuf,
+    /// The tenant that owns the fragment (if any)
+    pub tenant: Option<String>,
+    /// The fragment contents
+    pub data: Vec<u8>
+}
+
+
+/// The status of a fragment within the last scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentStatus {
+    /// The fragment has been accepted
+    Accepted,
+    /// The fragment is still being written, so its last stable version (if any) is used
+    Deferred,
+    /// The fragment has been rejected for the given reason
+    Rejected(String)
+}
+
+
+/// The state of a fragment within the last scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentState {
+    /// The path to the fragment
+    pub path: PathBuf,
+    /// The digest of the fragment contents (if they have been read; see [`config::digest`])
+    pub digest: Option<String>,
+    /// The status of the fragment
+    pub status: FragmentStatus
+}
+
+
+/// The result of a single scan
+#[derive(Debug, Default)]
+struct Scan {
+    /// The accepted fragments
+    fragments: Vec<Fragment>,
+    /// The rejection messages
+    rejections: Vec<String>,
+    /// The states of all fragments that match the pattern and are not ignored
+    states: Vec<FragmentState>,
+    /// Whether the scan must be refused due to limit violations
+    refused: bool
+}
+impl Scan {
+    /// Records that the fragment at `path` with the `digest` (if known) is skipped for `reason`
+    pub fn skip<R>(&mut self, path: &Path, digest: Option<String>, reason: R) where R: ToString {
+        let reason = reason.to_string();
+        self.rejections.push(format!("Skipping {}: {}", path.display(), reason));
+        self.states.push(FragmentState { path: path.to_path_buf(), digest, status: FragmentStatus::Rejected(reason) });
+    }
+    /// Records the `status` of the fragment at `path` with the contents `data` (if any)
+    pub fn record(&mut self, path: &Path, data: Option<&[u8]>, status: FragmentStatus) {
+        let digest = data.map(config::digest);
+        self.states.push(FragmentState { path: path.to_path_buf(), digest, status });
+    }
+}
+
+
+/// A log that reports each rejected fragment only once while it remains rejected
+#[derive(Debug, Clone, Default)]
+struct RejectionLog {
+    /// The currently reported rejections (shared between all clones)
+    reported: Arc<Mutex<HashSet<String>>>
+}
+impl RejectionLog {
+    /// Logs all rejections of the current scan that have not been reported yet and returns their amount
+    pub fn report(&self, rejections: Vec<String>) -> usize {
+        let mut reported = self.reported.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut new = 0;
+        for rejection in &rejections {
+            if !reported.contains(rejection) {
+                log::warn("fragment_rejected", rejection).emit();
+                new += 1;
+            }
+        }
+        *reported = rejections.into_iter().collect();
+        new
+    }
+}
+
+
+/// A directory containing config fragments
+#[derive(Debug, Clone)]
+pub struct Inbox<P> {
+    /// The directory containing the config fragments
+    directory: PathBuf,
+    /// The file pattern for config fragments
+    pattern: P,
+    /// The tracker to detect fragments that are still being written
+    stability: StabilityTracker,
+    /// The transaction mode
+    transactions: TransactionMode,
+    /// The symlink policy
+    symlinks: SymlinkPolicy,
+    /// The admission checks
+    admission: Admission,
+    /// The trusted keys to verify fragment signatures (or `None` if signatures are not required)
+    trusted_keys: Option<TrustedKeys>,
+    /// The directive policy (or `None` if fragments are unrestricted)
+    policy: Option<Policy>,
+    /// The size and count limits
+    limits: Limits,
+    /// The log for rejected fragments
+    rejections: RejectionLog,
+    /// The fragment states of the last scan (shared between all clones)
+    states: Arc<Mutex<Vec<FragmentState>>>,
+    /// The last verified contents of the signed fragments (shared between all clones)
+    verified: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+    /// The metrics for scans and rejections
+    metrics: Metrics
+}
+impl<P> Inbox<P> {
+    /// Creates a new inbox handle
+    pub fn new<D>(directory: D, pattern: P) -> Self where D: Into<PathBuf> {
+        let stability = StabilityTracker::new(Duration::ZERO);
+        let (transactions, symlinks) = (TransactionMode::Off, SymlinkPolicy::Follow);
+        Self {
+            directory: directory.into(), pattern, stability, transactions, symlinks,
+            admission: Admission::new(false), trusted_keys: None, policy: None,
+            limits: Limits::default(), rejections: Default::default(), states: Default::default(),
+            verified: Default::default(), metrics: Metrics::new()
+        }
+    }
+    /// Only considers fragments once they have settled for `window` (see [`StabilityTracker`])
+    pub fn with_settle_window(mut self, window: Duration) -> Self {
+        self.stability = StabilityTracker::new(window);
+        self
+    }
+    /// Sets the transaction mode
+    pub fn with_transactions(mut self, mode: TransactionMode) -> Self {
+        self.transactions = mode;
+        self
+    }
+    /// Sets the symlink policy
+    pub fn with_symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+    /// Sets the admission checks
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.admission = admission;
+        self
+    }
+    /// Requires each fragment to have a detached signature made by one of the trusted keys
+    pub fn with_trusted_keys(mut self, trusted_keys: Option<TrustedKeys>) -> Self {
+        self.trusted_keys = trusted_keys;
+        self
+    }
+    /// Restricts the sections and directives fragments may contain
+    pub fn with_policy(mut self, policy: Option<Policy>) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// Sets the size and count limits
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    /// Records the scan durations and rejected fragments to `metrics`
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The inbox directory
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+    /// The transaction mode
+    pub fn transactions(&self) -> TransactionMode {
+        self.transactions
+    }
+    /// The trusted keys (if signatures are required)
+    pub fn trusted_keys(&self) -> Option<&TrustedKeys> {
+        self.trusted_keys.as_ref()
+    }
+    /// Whether some fragments are still being written
+    pub fn is_settling(&self) -> bool {
+        self.stability.is_settling()
+    }
+    /// The point in time at which the next fragment that is still being written settles (if any)
+    pub fn settle_deadline(&self) -> Option<Instant> {
+        self.stability.settle_deadline()
+    }
+    /// The states of the fragments within the last scan, sorted by name
+    pub fn states(&self) -> Vec<FragmentState> {
+        self.states.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Lists and reads all fragments that match the pattern and are not ignored, sorted by name
+    ///
+    /// # Note
+    /// Fragments that are still being written are represented by their last stable contents or skipped if there is no
+    /// stable version yet. Fragments that violate the symlink policy or the admission checks, or that lack a valid signature
+    /// (if required), or that violate the directive policy or the limits, are skipped and logged. If the limit action is
+    /// [`LimitAction::Refuse`], limit violations fail the entire scan instead. Fragments that are removed while scanning are
+    /// skipped; if the inbox or a fragment cannot be read, the scan fails with an I/O error.
+    ///
+    /// If the inbox is a Kubernetes projected volume (ConfigMap/Secret), only the user-visible names are considered and
+    /// all fragments are read from the same snapshot, so that an atomic `..data` swap is observed as a single change.
+    pub fn fragments(&self) -> Result<Vec<Fragment>> where P: FilePattern {
+        // Retry if the projected volume has been swapped during the scan
+        const MAX_ATTEMPTS: usize = 3;
+        let mut attempt = 1;
+        loop {
+            let started = Instant::now();
+            let snapshot = self.projected_snapshot();
+            let Scan { mut fragments, mut rejections, mut states, refused } = self.scan(snapshot.as_deref())?;
+            self.metrics.scanned(started.elapsed());
+            if attempt >= MAX_ATTEMPTS || snapshot == self.projected_snapshot() {
+                // Enforce the policy
+                if let Some(policy) = &self.policy {
+                    let mut violations = Vec::new();
+                    fragments = policy.enforce(fragments, &mut violations);
+                    for (path, reason) in violations {
+                        rejections.push(format!("Rejecting {}: {}", path.display(), reason));
+                        if let Some(state) = states.iter_mut().find(|state| state.path == path) {
+                            state.status = FragmentStatus::Rejected(reason);
+                        }
+                    }
+                }
+                self.metrics.rejected(self.rejections.report(rejections));
+                *self.states.lock().unwrap_or_else(PoisonError::into_inner) = states;
+
+                // Refuse the fragments if they violate the limits
+                return match refused {
+                    true => Err(Error::Refused("some fragments violate the size or count limits".to_string())),
+                    false => Ok(fragments)
+                };
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Resolves the current snapshot directory if the inbox is a Kubernetes projected volume
+    fn projected_snapshot(&self) -> Option<PathBuf> {
+        // The data link must point to a hidden directory directly within the inbox
+        let target = fs::read_link(self.directory.join(PROJECTED_DATA)).ok()?;
+        let mut components = target.components();
+        let (Some(Component::Normal(name)), None) = (components.next(), components.next()) else {
+            let message = format!("Ignoring {}: invalid projected volume link target {}", PROJECTED_DATA, target.display());
+            log::warn("projected_volume", message).emit();
+            return None;
+        };
+        match name.as_bytes().starts_with(b"..") {
+            true => Some(self.directory.join(name)),
+            false => None
+        }
+    }
+
+    /// Scans the inbox or the given projected volume snapshot
+    fn scan(&self, snapshot: Option<&Path>) -> Result<Scan> where P: FilePattern {
+        // List and sort the entries
+        let source = snapshot.unwrap_or(&self.directory);
+        let ignore = IgnoreRules::load(source);
+        let files = fsext::list_files(source).context("list", source)?;
+        let mut names: Vec<_> = files.into_iter().filter_map(|path| path.file_name().map(OsString::from)).collect();
+        names.sort();
+
+        let paths: Vec<_> = names.iter().map(|name| self.directory.join(name)).collect();
+
+        // Load the trusted keys
+        let mut scan = Scan::default();
+        let keys = self.trusted_keys.as_ref().map(|trusted_keys| trusted_keys.load(&mut scan.rejections));
+
+        // Read all fragments
+        let (mut total_size, mut observed) = (0u64, Vec::new());
+        'read_loop: for (name, path) in names.into_iter().zip(paths) {
+            // Check if the name is ignored or does not match the pattern
+            let name_bytes = fsext::path_bytes(&name);
+            if ignore.matches(&name_bytes) || !self.pattern.matches(&name_bytes) {
+                continue 'read_loop;
+            }
+
+            // Open the file according to the symlink policy
+            let file = match self.symlinks.open(source, &name) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue 'read_loop,
+                Err(e) => {
+                    scan.skip(&path, None, e);
+                    continue 'read_loop;
+                }
+            };
+
+            // Check the ownership and permissions
+            let admitted = file.metadata().map_err(|e| e.to_string())
+                .and_then(|metadata| Ok((self.admission.check(&metadata)?, metadata.len())));
+            let (tenant, size) = match admitted {
+                Ok(admitted) => admitted,
+                Err(e) => {
+                    scan.skip(&path, None, e);
+                    continue 'read_loop;
+                }
+            };
+
+            // Check the limits
+            if let Err(e) = self.limits.check(size, scan.fragments.len(), total_size) {
+                scan.skip(&path, None, e);
+                scan.refused |= self.limits.action == LimitAction::Refuse;
+                continue 'read_loop;
+            }
+
+            // Read the file if it is stable; projected volumes are updated atomically and thus always stable
+            let max_size = self.limits.max_fragment_size;
+            let data = match snapshot {
+                Some(_) => fsext::read_limited(file, max_size).map(Some),
+                None => {
+                    observed.push(path.clone());
+                    self.stability.read(&path, file, max_size)
+                }
+            };
+            let mut data = match data {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    scan.record(&path, None, FragmentStatus::Deferred);
+                    continue 'read_loop;
+                },
+                Err(e) if e.kind() == ErrorKind::FileTooLarge => {
+                    scan.skip(&path, None, "fragment has grown beyond the size limit");
+                    scan.refused |= self.limits.action == LimitAction::Refuse;
+                    continue 'read_loop;
+                },
+                Err(e) => return Err(e).context("read", path)
+            };
+
+            // Verify the signature; the signature is tracked like the fragment, and the last verified version is kept until
+            // both have settled, since they cannot be replaced atomically
+            let mut settled = snapshot.is_some() || self.stability.is_settled(&path);
+            if let Some(keys) = &keys {
+                let mut signature_name = name.clone();
+                signature_name.push(SIGNATURE_EXT);
+                let signature_path = self.directory.join(&signature_name);
+                const MAX_SIGNATURE_SIZE: u64 = 4096;
+                let signature = self.symlinks.open(source, &signature_name).and_then(|file| match snapshot {
+                    Some(_) => fsext::read_limited(file, MAX_SIGNATURE_SIZE).map(Some),
+                    None => {
+                        observed.push(signature_path.clone());
+                        self.stability.read(&signature_path, file, MAX_SIGNATURE_SIZE)
+                    }
+                });
+                settled &= snapshot.is_some() || self.stability.is_settled(&signature_path);
+                let verified = match signature {
+                    Ok(Some(signature)) => keys.verify(&data, &signature),
+                    Ok(None) => Err("signature is still being written".to_string()),
+                    Err(e) => Err(format!("failed to read signature: {}", e))
+                };
+                let mut last_verified = self.verified.lock().unwrap_or_else(PoisonError::into_inner);
+                match (verified, last_verified.get(&path)) {
+                    (Ok(()), _) => drop(last_verified.insert(path.clone(), data.clone())),
+                    (Err(_), Some(last)) if !settled => data = last.clone(),
+                    (Err(_), None) if !settled => {
+                        scan.record(&path, None, FragmentStatus::Deferred);
+                        continue 'read_loop;
+                    },
+                    (Err(e), _) => {
+                        scan.skip(&path, Some(config::digest(&data)), e);
+                        continue 'read_loop;
+                    }
+                }
+            }
+
+            // Accept the fragment (or its last stable version)
+            let status = match settled {
+                true => FragmentStatus::Accepted,
+                false => FragmentStatus::Deferred
+            };
+            scan.record(&path, Some(&data), status);
+            total_size = total_size.saturating_add(data.len() as u64);
+            scan.fragments.push(Fragment { path, tenant, data });
+        }
+
+        // Forget fragments that have been removed or skipped
+        self.stability.retain(&observed);
+        let mut verified = self.verified.lock().unwrap_or_else(PoisonError::into_inner);
+        verified.retain(|path, _| observed.contains(path));
+        Ok(scan)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext::FileExtensionPattern;
+    use ed25519_dalek::{ Signer, SigningKey };
+    use std::{ fs::{ File, FileTimes }, os::unix::fs::symlink, time::SystemTime };
+
+    /// Writes `data` to `path` and backdates its modification time by `age`
+    fn write(path: &Path, data: &[u8], age: Duration) {
+        fs::write(path, data).expect("failed to write file");
+        let file = File::options().write(true).open(path).expect("failed to open file");
+        file.set_times(FileTimes::new().set_modified(SystemTime::now() - age)).expect("failed to set modification time");
+    }
+
+    /// Creates a Kubernetes projected volume snapshot `name` with the given fragments within `inbox` and activates it
+    fn project(inbox: &Path, name: &str, fragments: &[(&str, &str)]) {
+        let snapshot = inbox.join(name);
+        fs::create_dir(&snapshot).expect("failed to create snapshot");
+        for (fragment, data) in fragments {
+            fs::write(snapshot.join(fragment), data).expect("failed to write fragment");
+            let _ = symlink(Path::new(PROJECTED_DATA).join(fragment), inbox.join(fragment));
+        }
+
+        // Swap the data link atomically
+        symlink(name, inbox.join("..data_tmp")).expect("failed to create data link");
+        fs::rename(inbox.join("..data_tmp"), inbox.join(PROJECTED_DATA)).expect("failed to swap data link");
+    }
+
+    /// Scans `inbox` and returns the names and contents of the accepted fragments
+    fn scan(inbox: &Inbox<FileExtensionPattern>) -> Vec<(String, String)> {
+        let fragments = inbox.fragments().expect("failed to scan inbox");
+        fragments.into_iter().map(|fragment| {
+            let name = fragment.path.file_name().expect("invalid path").to_string_lossy().to_string();
+            (name, String::from_utf8(fragment.data).expect("invalid fragment"))
+        }).collect()
+    }
+
+    #[test]
+    fn projected_volumes_are_read_from_the_current_snapshot() {
+        let directory = fsext::test_dir("inbox-projected");
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg")).with_settle_window(Duration::from_secs(60));
+        project(&directory, "..2026_10_18_12_00_00.1", &[("a.cfg", "a1"), ("b.cfg", "b1")]);
+        assert_eq!(inbox.projected_snapshot(), Some(directory.join("..2026_10_18_12_00_00.1")));
+
+        // Snapshots are complete, so their fragments are not deferred, and the hidden entries are not fragments
+        let expected = [("a.cfg".to_string(), "a1".to_string()), ("b.cfg".to_string(), "b1".to_string())];
+        assert_eq!(scan(&inbox), expected);
+        assert!(inbox.states().iter().all(|state| state.status == FragmentStatus::Accepted));
+        assert!(inbox.states().iter().all(|state| state.path.parent() == Some(directory.as_path())));
+
+        // A swap is observed as a whole
+        project(&directory, "..2026_10_18_12_05_00.2", &[("a.cfg", "a2"), ("b.cfg", "b2")]);
+        let expected = [("a.cfg".to_string(), "a2".to_string()), ("b.cfg".to_string(), "b2".to_string())];
+        assert_eq!(scan(&inbox), expected);
+    }
+
+    #[test]
+    fn invalid_projected_links_are_ignored() {
+        let directory = fsext::test_dir("inbox-projected-invalid");
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg"));
+        fs::write(directory.join("a.cfg"), "a").expect("failed to write fragment");
+
+        // The data link must point to a hidden directory within the inbox
+        symlink("/etc", directory.join(PROJECTED_DATA)).expect("failed to create data link");
+        assert_eq!(inbox.projected_snapshot(), None);
+        fs::remove_file(directory.join(PROJECTED_DATA)).expect("failed to remove data link");
+        symlink("snapshot", directory.join(PROJECTED_DATA)).expect("failed to create data link");
+        assert_eq!(inbox.projected_snapshot(), None);
+
+        // The inbox is scanned as usual then
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "a".to_string())]);
+    }
+
+    #[test]
+    fn signatures_keep_the_last_verified_version() {
+        let (directory, keys) = (fsext::test_dir("inbox-signatures"), fsext::test_dir("inbox-signatures-keys"));
+        let key = SigningKey::from_bytes(&[7; 32]);
+        fs::write(keys.join("key"), key.verifying_key().as_bytes()).expect("failed to write key");
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg")).with_settle_window(Duration::from_secs(60))
+            .with_trusted_keys(Some(TrustedKeys::new(keys)));
+        let (fragment, signature) = (directory.join("a.cfg"), directory.join("a.cfg.sig"));
+        let (settled, fresh) = (Duration::from_secs(120), Duration::ZERO);
+
+        // A settled fragment with a valid signature is accepted
+        write(&fragment, b"v1", settled);
+        write(&signature, &key.sign(b"v1").to_bytes(), settled);
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "v1".to_string())]);
+
+        // While the signature is still being written, the last verified version is kept
+        write(&fragment, b"v2", settled);
+        write(&signature, &key.sign(b"v2").to_bytes(), fresh);
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "v1".to_string())]);
+        assert_eq!(inbox.states()[0].status, FragmentStatus::Deferred);
+        assert!(inbox.is_settling());
+
+        // Once both have settled, the new version is accepted
+        write(&signature, &key.sign(b"v2").to_bytes(), settled);
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "v2".to_string())]);
+        assert_eq!(inbox.states()[0].status, FragmentStatus::Accepted);
+
+        // A settled fragment whose settled signature does not match is rejected
+        write(&fragment, b"v3", settled);
+        assert_eq!(scan(&inbox), []);
+        assert!(matches!(inbox.states()[0].status, FragmentStatus::Rejected(_)));
+    }
+
+    #[test]
+    fn limit_actions() {
+        let directory = fsext::test_dir("inbox-limits");
+        fs::write(directory.join("a.cfg"), "small").expect("failed to write fragment");
+        fs::write(directory.join("b.cfg"), "too large").expect("failed to write fragment");
+        fs::write(directory.join("c.cfg"), "small").expect("failed to write fragment");
+        let limits = Limits { max_fragment_size: 5, max_fragments: 10, max_total_size: 100, action: LimitAction::Exclude };
+
+        // Violating fragments are either excluded or refuse the entire scan
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg")).with_limits(limits);
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "small".to_string()), ("c.cfg".to_string(), "small".to_string())]);
+        let limits = Limits { action: LimitAction::Refuse, ..limits };
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg")).with_limits(limits);
+        assert!(matches!(inbox.fragments(), Err(Error::Refused(_))));
+    }
+
+    #[test]
+    fn missing_inbox() {
+        let directory = fsext::test_dir("inbox-missing").join("missing");
+        let inbox = Inbox::new(&directory, FileExtensionPattern::new(".cfg"));
+        assert!(matches!(inbox.fragments(), Err(Error::Io { operation: "list", .. })));
+
+        // The inbox is scanned again once it appears
+        fs::create_dir(&directory).expect("failed to create inbox");
+        fs::write(directory.join("a.cfg"), "a").expect("failed to write fragment");
+        assert_eq!(scan(&inbox), [("a.cfg".to_string(), "a".to_string())]);
+    }
+}
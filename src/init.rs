@@ -0,0 +1,100 @@
+use crate::error::{ Context, Result };
+use std::{ io, str::FromStr };
+
+
+/// Whether the daemon acts as init process (i.e. reaps orphaned descendants)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitMode {
+    /// The init mode is enabled if the daemon runs as PID 1
+    Auto,
+    /// The init mode is always enabled
+    On,
+    /// The init mode is disabled
+    Off
+}
+impl InitMode {
+    /// Whether the init mode is enabled
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Self::Auto => std::process::id() == 1,
+            Self::On => true,
+            Self::Off => false
+        }
+    }
+}
+impl FromStr for InitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "on" => Ok(Self::On),
+            "off" => Ok(Self::Off),
+            s => Err(format!("Invalid init mode: {} (expected auto, on or off)", s))
+        }
+    }
+}
+
+
+/// Registers the daemon as subreaper, so that orphaned descendants are re-parented to the daemon instead of PID 1
+///
+/// # Note
+/// This is a no-op if the daemon is PID 1 already.
+pub fn become_subreaper() -> Result {
+    // SAFETY: `PR_SET_CHILD_SUBREAPER` has no memory-safety preconditions
+    let result = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1 as libc::c_ulong, 0, 0, 0) };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()).operation("register as subreaper")
+    }
+}
+
+
+/// Reaps all exited children; `reap_own` is called instead if the exited child is `own` (so that its exit status can be
+/// collected by its owner)
+pub fn reap<F>(own: Option<u32>, mut reap_own: F) -> Result where F: FnMut() -> io::Result<()> {
+    loop {
+        // Peek the next exited child without reaping it
+        // SAFETY: `siginfo_t` is a plain C struct that is filled by `waitid`
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+        // SAFETY: `info` is a valid `siginfo_t` struct
+        let result = unsafe { libc::waitid(libc::P_ALL, 0, &mut info, flags) };
+        if result != 0 {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ECHILD) => return Ok(()),
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                e => return Err(e).operation("wait for children")
+            }
+        }
+
+        // Reap the child
+        // SAFETY: `si_pid` is valid since `waitid` has been called with `WEXITED`
+        let pid = unsafe { info.si_pid() };
+        match pid {
+            0 => return Ok(()),
+            pid if Some(pid as u32) == own => reap_own().operation("reap child process")?,
+            // SAFETY: `waitpid` has no memory-safety preconditions if the status pointer is NULL
+            pid => unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG); }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_modes() {
+        assert_eq!("auto".parse(), Ok(InitMode::Auto));
+        assert_eq!("on".parse(), Ok(InitMode::On));
+        assert_eq!("off".parse(), Ok(InitMode::Off));
+        assert!("yes".parse::<InitMode>().is_err());
+
+        // The test runner is never PID 1
+        assert!(!InitMode::Auto.is_enabled());
+        assert!(InitMode::On.is_enabled());
+        assert!(!InitMode::Off.is_enabled());
+    }
+}
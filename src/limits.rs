@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+
+/// The action if a fragment violates the limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    /// The violating fragments are excluded
+    Exclude,
+    /// The reload is refused and the current config is kept
+    Refuse
+}
+impl FromStr for LimitAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exclude" => Ok(Self::Exclude),
+            "refuse" => Ok(Self::Refuse),
+            s => Err(format!("Invalid limit action: {} (expected exclude or refuse)", s))
+        }
+    }
+}
+
+
+/// Size and count limits for fragments
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The maximum size of a single fragment in bytes
+    pub max_fragment_size: u64,
+    /// The maximum amount of fragments
+    pub max_fragments: usize,
+    /// The maximum total size of all fragments in bytes
+    pub max_total_size: u64,
+    /// The action if a fragment violates the limits
+    pub action: LimitAction
+}
+impl Limits {
+    /// Checks whether a fragment of `size` bytes may be added to `count` fragments with a total size of `total` bytes
+    pub fn check(&self, size: u64, count: usize, total: u64) -> Result<(), String> {
+        if size > self.max_fragment_size {
+            return Err(format!("fragment size of {} bytes exceeds the limit of {} bytes", size, self.max_fragment_size));
+        }
+        if count >= self.max_fragments {
+            return Err(format!("fragment count exceeds the limit of {} fragments", self.max_fragments));
+        }
+        if total.saturating_add(size) > self.max_total_size {
+            return Err(format!("total size exceeds the limit of {} bytes", self.max_total_size));
+        }
+        Ok(())
+    }
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_fragment_size: u64::MAX, max_fragments: usize::MAX, max_total_size: u64::MAX, action: LimitAction::Exclude }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Limits of 10 bytes per fragment, 2 fragments and 15 bytes in total
+    const LIMITS: Limits =
+        Limits { max_fragment_size: 10, max_fragments: 2, max_total_size: 15, action: LimitAction::Exclude };
+
+    #[test]
+    fn parse_actions() {
+        assert_eq!("exclude".parse(), Ok(LimitAction::Exclude));
+        assert_eq!("refuse".parse(), Ok(LimitAction::Refuse));
+        assert!("ignore".parse::<LimitAction>().is_err());
+    }
+
+    #[test]
+    fn check_limits() {
+        assert_eq!(LIMITS.check(10, 0, 0), Ok(()));
+        assert_eq!(LIMITS.check(5, 1, 10), Ok(()));
+        assert_eq!(LIMITS.check(11, 0, 0), Err("fragment size of 11 bytes exceeds the limit of 10 bytes".to_string()));
+        assert_eq!(LIMITS.check(1, 2, 2), Err("fragment count exceeds the limit of 2 fragments".to_string()));
+        assert_eq!(LIMITS.check(6, 1, 10), Err("total size exceeds the limit of 15 bytes".to_string()));
+        assert_eq!(Limits::default().check(u64::MAX, usize::MAX - 1, u64::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    fmt::{ self, Display, Formatter, Write as _ },
+    io::{ self, Write as _ },
+    str::FromStr,
+    sync::OnceLock,
+    time::{ SystemTime, UNIX_EPOCH }
+};
+
+
+/// The global logger
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+
+/// A log level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Diagnostic messages
+    Debug,
+    /// Regular operation
+    Info,
+    /// Recoverable problems
+    Warn,
+    /// Failures
+    Error
+}
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Debug => write!(f, "debug"),
+            Self::Info => write!(f, "info"),
+            Self::Warn => write!(f, "warn"),
+            Self::Error => write!(f, "error")
+        }
+    }
+}
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            s => Err(format!("Invalid log level: {} (expected debug, info, warn or error)", s))
+        }
+    }
+}
+
+
+/// A log output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One human-readable line per record
+    Text,
+    /// One JSON object per line
+    Json
+}
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            s => Err(format!("Invalid log format: {} (expected text or json)", s))
+        }
+    }
+}
+
+
+/// A field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A string
+    String(String),
+    /// An unsigned integer
+    Unsigned(u64),
+    /// A signed integer
+    Signed(i64),
+    /// A floating point number
+    Float(f64),
+    /// A boolean
+    Bool(bool),
+    /// A list of strings
+    List(Vec<String>),
+    /// An array of values
+    Array(Vec<Value>),
+    /// An object with named values
+    Object(Vec<(&'static str, Value)>)
+}
+impl Value {
+    /// Writes the value as JSON
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Self::String(string) => write_json_string(string, out),
+            Self::Unsigned(number) => { let _ = write!(out, "{}", number); },
+            Self::Signed(number) => { let _ = write!(out, "{}", number); },
+            Self::Float(number) if number.is_finite() => { let _ = write!(out, "{}", number); },
+            Self::Float(_) => out.push_str("null"),
+            Self::Bool(bool) => { let _ = write!(out, "{}", bool); },
+            Self::List(list) => {
+                out.push('[');
+                for (index, string) in list.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(string, out);
+                }
+                out.push(']');
+            },
+            Self::Array(values) => {
+                out.push('[');
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    value.write_json(out);
+                }
+                out.push(']');
+            },
+            Self::Object(fields) => out.push_str(&json(fields.iter().map(|(name, value)| (*name, value))))
+        }
+    }
+}
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Self::Unsigned(value.into())
+    }
+}
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Self::Unsigned(value)
+    }
+}
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Self::Unsigned(value as u64)
+    }
+}
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Self::Signed(value.into())
+    }
+}
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+impl From<Vec<String>> for Value {
+    fn from(value: Vec<String>) -> Self {
+        Self::List(value)
+    }
+}
+
+
+/// A log record that is written once it is emitted
+#[derive(Debug, Clone)]
+#[must_use = "the record is only written if it is emitted"]
+pub struct Record {
+    /// The log level
+    level: Level,
+    /// The event type (e.g. `reload`)
+    event: &'static str,
+    /// The human-readable message
+    message: String,
+    /// The additional fields
+    fields: Vec<(&'static str, Value)>
+}
+impl Record {
+    /// Creates a new record
+    pub fn new<M>(level: Level, event: &'static str, message: M) -> Self where M: ToString {
+        Self { level, event, message: message.to_string(), fields: Vec::new() }
+    }
+
+    /// Adds the field `name`
+    pub fn field<V>(mut self, name: &'static str, value: V) -> Self where V: Into<Value> {
+        self.fields.push((name, value.into()));
+        self
+    }
+    /// Adds the field `error` with the error details
+    pub fn error<E>(self, error: E) -> Self where E: Display {
+        self.field("error", error.to_string())
+    }
+
+    /// Writes the record to stderr if its level is enabled
+    pub fn emit(self) {
+        let logger = LOGGER.get().copied().unwrap_or_default();
+        if self.level < logger.level {
+            return;
+        }
+
+        // Format the record and write it at once so that concurrent lines are not interleaved
+        let mut line = match logger.format {
+            Format::Text => format!("[{}] {}", self.level, self.message),
+            Format::Json => self.to_json()
+        };
+        line.push('\n');
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    /// Formats the record as JSON object
+    fn to_json(&self) -> String {
+        let header = [
+            ("time", Value::String(timestamp(SystemTime::now()))), ("level", Value::String(self.level.to_string())),
+            ("event", Value::from(self.event)), ("message", Value::String(self.message.clone()))
+        ];
+        let fields = self.fields.iter().map(|(name, value)| (*name, value));
+        json(header.iter().map(|(name, value)| (*name, value)).chain(fields))
+    }
+}
+
+
+/// The logger configuration
+#[derive(Debug, Clone, Copy)]
+struct Logger {
+    /// The minimum level
+    level: Level,
+    /// The output format
+    format: Format
+}
+impl Default for Logger {
+    fn default() -> Self {
+        Self { level: Level::Info, format: Format::Text }
+    }
+}
+
+
+/// Configures the global logger; records emitted before are written with level `info` and text format
+pub fn init(level: Level, format: Format) {
+    let _ = LOGGER.set(Logger { level, format });
+}
+
+
+/// Creates a new debug record
+pub fn debug<M>(event: &'static str, message: M) -> Record where M: ToString {
+    Record::new(Level::Debug, event, message)
+}
+/// Creates a new info record
+pub fn info<M>(event: &'static str, message: M) -> Record where M: ToString {
+    Record::new(Level::Info, event, message)
+}
+/// Creates a new warning record
+pub fn warn<M>(event: &'static str, message: M) -> Record where M: ToString {
+    Record::new(Level::Warn, event, message)
+}
+/// Creates a new error record
+pub fn error<M>(event: &'static str, message: M) -> Record where M: ToString {
+    Record::new(Level::Error, event, message)
+}
+
+
+/// Formats `fields` as JSON object
+pub fn json<'a, F>(fields: F) -> String where F: IntoIterator<Item = (&'a str, &'a Value)> {
+    let mut json = String::from("{");
+    for (index, (name, value)) in fields.into_iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        write_json_string(name, &mut json);
+        json.push(':');
+        value.write_json(&mut json);
+    }
+    json.push('}');
+    json
+}
+
+
+/// Formats `time` as RFC 3339 UTC timestamp with millisecond precision (see [`timestamp`])
+pub fn now() -> String {
+    timestamp(SystemTime::now())
+}
+
+
+/// Writes `string` as quoted and escaped JSON string
+fn write_json_string(string: &str, out: &mut String) {
+    out.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if char.is_control() => { let _ = write!(out, "\\u{:04x}", char as u32); },
+            char => out.push(char)
+        }
+    }
+    out.push('"');
+}
+
+
+/// Formats `time` as RFC 3339 UTC timestamp with millisecond precision
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = (since_epoch.as_secs() / 86400, since_epoch.as_secs() % 86400);
+
+    // Convert the days since the epoch into a civil date (see http://howardhinnant.github.io/date_algorithms.html)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day, hour, minute, second,
+        since_epoch.subsec_millis())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse() {
+        assert_eq!("debug".parse(), Ok(Level::Debug));
+        assert_eq!("warn".parse(), Ok(Level::Warn));
+        assert!("trace".parse::<Level>().is_err());
+        assert!(Level::Debug < Level::Info && Level::Warn < Level::Error);
+        assert_eq!("text".parse(), Ok(Format::Text));
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert!("yaml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn json_values() {
+        let (object, list) = (Value::Object(vec![("a", Value::from(1u32))]), Value::from(vec!["x".to_string()]));
+        let fields = [
+            ("string", Value::from("quote \" backslash \\ newline \n bell \u{7}")), ("signed", Value::from(-1)),
+            ("float", Value::from(0.5)), ("nan", Value::from(f64::NAN)), ("bool", Value::from(true)),
+            ("array", Value::Array(vec![list, object]))
+        ];
+        assert_eq!(json(fields.iter().map(|(name, value)| (*name, value))), concat!(
+            r#"{"string":"quote \" backslash \\ newline \n bell \u0007","signed":-1,"float":0.5,"nan":null,"#,
+            r#""bool":true,"array":[["x"],{"a":1}]}"#
+        ));
+    }
+
+    #[test]
+    fn records() {
+        let record = info("reload", "Reloaded").field("generation", 3u64).error("failed");
+        let json = record.to_json();
+        assert!(json.starts_with(r#"{"time":""#));
+        assert!(json.ends_with(r#","level":"info","event":"reload","message":"Reloaded","generation":3,"error":"failed"}"#));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(951_782_400_123);
+        assert_eq!(timestamp(leap_day), "2000-02-29T00:00:00.123Z");
+        let new_year = UNIX_EPOCH + Duration::from_secs(1_767_225_599);
+        assert_eq!(timestamp(new_year), "2025-12-31T23:59:59.000Z");
+    }
+}
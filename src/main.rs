@@ -1,86 +1,597 @@
+mod error;
+mod env;
+mod log;
+mod admission;
+mod backoff;
 mod fsext;
+mod guard;
+mod ignore;
+mod limits;
+mod inbox;
+mod policy;
+mod signature;
+mod stability;
+mod symlink;
+mod transaction;
 mod events;
 mod child;
+mod init;
+mod metrics;
+mod admin;
+mod signal;
+mod supervisor;
+mod syslog;
+mod traffic;
 mod config;
 
 use crate::{
-    config::Config, fsext::FileExtensionPattern, child::ChildProcess,
-    events::{ EventSource, directory::DirectoryEventSource, signals::SignalEventSource }
+    admin::Admin, admission::{ Admission, IdList, TenantMap }, backoff::Backoff, config::{ Assembly, Config },
+    error::{ Context, Error, Result }, env::LoopbackAddr, guard::DeletionGuard, limits::{ LimitAction, Limits },
+    fsext::{ FileExtensionPattern, FilePattern }, inbox::Inbox, child::ChildProcess, policy::Policy,
+    signature::TrustedKeys, symlink::SymlinkPolicy, transaction::TransactionMode, init::InitMode,
+    log::{ Format, Level, Record }, metrics::{ Metrics, Reload }, signal::{ SignalAction, SignalMap },
+    supervisor::{ CrashAction, Decision, RestartPolicy, Supervisor }, syslog::{ SocketMode, SocketOwner }, traffic::Traffic,
+    events::{
+        child::ChildExit, directory::{ Baseline, DirectoryEventSource }, http::{ self, HttpEventSource },
+        output::ChildOutput, reactor::Reactor, signals::SignalEventSource, syslog::SyslogEventSource,
+        timer::TimerEventSource
+    }
 };
 use std::{
     process,
-    sync::mpsc::{ self, Sender, Receiver }
+    io::{ self, Write },
+    net::SocketAddr,
+    path::PathBuf,
+    time::{ Duration, Instant }
 };
 
 
 /// The HAProxy config dir
-const CONFIG_DIR: &'static str = "/usr/local/etc/haproxy.inbox";
+const CONFIG_DIR: &str = "/usr/local/etc/haproxy.inbox";
 /// The name of the assembled config file
-const CONFIG_FILE: &'static str = "/usr/local/etc/haproxy/haproxy.cfg";
+const CONFIG_FILE: &str = "/usr/local/etc/haproxy/haproxy.cfg";
 /// The extension for config files
-const CONFIG_FILE_EXT: &'static str = ".cfg";
+const CONFIG_FILE_EXT: &str = ".cfg";
 /// The haproxy binary
-const HAPROXY_BIN: &'static str = "/usr/local/sbin/haproxy";
+const HAPROXY_BIN: &str = "/usr/local/sbin/haproxy";
+/// The environment variable that passes the syslog listener address to HAProxy
+const SYSLOG_ADDR_VAR: &str = "AUTOCONFD_SYSLOG_ADDR";
+/// The default permissions of the syslog socket; not writable for everyone, since any local process could inject log lines
+/// otherwise, so HAProxy must be granted access via the socket owner if it drops its privileges
+const SYSLOG_SOCKET_MODE: SocketMode = SocketMode(0o660);
+/// The default time in milliseconds a fragment must remain unchanged before it is considered complete
+const SETTLE_MS: u64 = 1000;
+/// The default maximum size of a single fragment in bytes
+const MAX_FRAGMENT_SIZE: u64 = 1024 * 1024;
+/// The default maximum amount of fragments
+const MAX_FRAGMENTS: usize = 4096;
+/// The default maximum total size of all fragments in bytes
+const MAX_TOTAL_SIZE: u64 = 64 * 1024 * 1024;
+/// The default maximum percentage of fragments that may be removed at once
+const MAX_REMOVAL_PERCENT: usize = 100;
+/// The default minimum amount of fragments
+const MIN_FRAGMENTS: usize = 1;
+/// The default maximum amount of HAProxy restarts within the restart window
+const MAX_RESTARTS: usize = 5;
+/// The default restart window in seconds
+const RESTART_WINDOW_S: u64 = 60;
+/// The default time in seconds HAProxy may take to drain its connections on shutdown before it is killed
+const DRAIN_TIMEOUT_S: u64 = 8;
+/// The default interval in seconds at which the traffic summary is logged (`0` disables the summary)
+const TRAFFIC_SUMMARY_S: u64 = 0;
+/// The maximum amount of times dead event sources are restarted within the source restart window
+const MAX_SOURCE_RESTARTS: usize = 5;
+/// The window within which event source restarts are counted
+const SOURCE_RESTART_WINDOW: Duration = Duration::from_secs(600);
+/// The interval at which the initial config assembly is retried while the inbox is in the middle of a transaction
+const TRANSACTION_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 
-/// An event
+/// An event source
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Source {
+    /// The directory event source
+    Directory,
+    /// The signal event source
+    Signal,
+    /// The child process event source
+    Child,
+    /// The child process output event source
+    Output,
+    /// The restart timer event source
+    Restart,
+    /// The drain timer event source
+    Drain,
+    /// The orphan reaper event source
+    Reaper,
+    /// The syslog event source
+    Syslog,
+    /// The traffic summary timer event source
+    Summary,
+    /// The metrics endpoint event source
+    Metrics,
+    /// The admin API event source
+    Admin
+}
+
+
+/// An event
+#[derive(Debug)]
 pub enum Event {
     /// A directory change event
     Directory,
-    /// A termination signal event
-    Signal,
-    /// A process event
-    Child
+    /// A signal event with the signal number and the configured action
+    Signal(libc::c_int, SignalAction),
+    /// A child process exit event
+    Child(ChildExit),
+    /// A line of child process output
+    Output(ChildOutput),
+    /// A delayed HAProxy restart is due
+    Restart,
+    /// The drain timeout during shutdown has passed
+    Drain,
+    /// Some children have exited and must be reaped
+    Reap,
+    /// A syslog message has been received
+    Syslog(syslog::Message),
+    /// The traffic summary is due
+    Summary,
+    /// A request to the metrics endpoint
+    Metrics(http::Request),
+    /// A request to the admin API
+    Admin(http::Request),
+    /// An event source failed
+    Failed(Source)
 }
-impl Event {
-    /// Creates an event channel pair
-    pub fn make_channels() -> (Sender<Self>, Receiver<Self>) {
-        mpsc::channel()
+
+
+pub fn main() {
+    if let Err(e) = run() {
+        log::error("fatal", format!("Fatal error: {}", e)).error(e).emit();
+        process::exit(1);
     }
 }
 
 
-pub fn main() {
+/// Adds the details of a HAProxy exit to `record`
+fn exit_fields(record: Record, exit: &ChildExit) -> Record {
+    record.field("pid", exit.pid).field("code", exit.code()).field("uptime_s", exit.uptime.as_secs_f64())
+        .field("generation", exit.generation)
+}
+
+
+/// Kills HAProxy; failures are logged since the drain timeout or a forced shutdown cannot do more
+fn kill(haproxy: &ChildProcess) {
+    if let Err(e) = haproxy.signal(libc::SIGKILL) {
+        log::error("haproxy_kill", format!("Failed to kill HAProxy: {}", e)).error(e).emit();
+    }
+}
+
+
+/// Remembers the current config if HAProxy has been stable with it, reassembles the config and restarts HAProxy; returns
+/// the outcome together with a description
+fn reload<P>(
+    config: &Config<P>, haproxy: &ChildProcess, supervisor: &Supervisor, restart_timer: &TimerEventSource, metrics: &Metrics
+) -> Result<(Reload, String)> where P: FilePattern {
+    // Remember the current config if HAProxy has been stable with it
+    let started = Instant::now();
+    if supervisor.is_stable(haproxy.uptime()?) {
+        config.mark_good();
+    }
+
+    // Apply the new config
+    match config.assemble() {
+        Ok(assembly) => {
+            restart_timer.disarm();
+            haproxy.restart(config.generation())?;
+            metrics.reload(Reload::Success);
+
+            // Log the applied change
+            let (generation, duration) = (config.generation(), started.elapsed());
+            let message = format!("Reloaded config generation {} in {}ms", generation, duration.as_millis());
+            let changed: Vec<_> = assembly.changed.iter().map(|path| path.display().to_string()).collect();
+            log::info("reloaded", &message).field("pid", haproxy.pid()?).field("generation", generation)
+                .field("changed_fragments", changed).field("fragments", assembly.fragments)
+                .field("config_size", assembly.size).field("config_digest", assembly.digest)
+                .field("duration_ms", duration.as_secs_f64() * 1000.0).emit();
+            Ok((Reload::Success, message))
+        },
+        Err(Error::Pending(reason)) => {
+            metrics.reload(Reload::Deferred);
+            let message = format!("Keeping the current config until the transaction is complete: {}", reason);
+            log::info("reload_deferred", &message).field("reason", reason).emit();
+            Ok((Reload::Deferred, message))
+        },
+        Err(Error::Refused(reason)) => {
+            metrics.reload(Reload::Refused);
+            let message = format!("Keeping the current config: {}", reason);
+            log::warn("reload_refused", &message).field("reason", reason).emit();
+            Ok((Reload::Refused, message))
+        },
+        Err(e) => {
+            metrics.reload(Reload::Failure);
+            let message = format!("Failed to assemble config: {}; keeping the current config", e);
+            log::error("reload_failed", &message).error(e).emit();
+            Ok((Reload::Failure, message))
+        }
+    }
+}
+
+
+/// Assembles the config for the first time and retries with a backoff while the inbox is unavailable; refused fragments
+/// are fatal since there is no config to keep
+///
+/// # Note
+/// While waiting for the next attempt, termination signals exit the daemon and orphaned descendants are reaped.
+fn assemble_initial<P>(config: &Config<P>, reactor: &mut Reactor<Event>, retry_timer: &TimerEventSource) -> Result<Assembly>
+where P: FilePattern {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(30));
+    loop {
+        match config.assemble() {
+            Ok(assembly) => return Ok(assembly),
+            Err(e @ Error::Refused(_)) => return Err(e),
+            Err(Error::Pending(reason)) => {
+                let message = format!("Waiting for the transaction to complete before the initial assembly: {}", reason);
+                log::info("assemble_deferred", message).field("reason", reason).emit();
+                retry_timer.arm(Instant::now() + TRANSACTION_RETRY_INTERVAL);
+            },
+            Err(e) => {
+                let delay = backoff.next();
+                let message = format!("Failed to assemble initial config: {}; retrying in {}s...", e, delay.as_secs_f32());
+                log::warn("assemble_failed", message).field("retry_s", delay.as_secs_f64()).error(e).emit();
+                retry_timer.arm(Instant::now() + delay);
+            }
+        }
+
+        // Wait for the next attempt
+        'wait: loop {
+            for event in reactor.wait()? {
+                match event {
+                    Event::Restart => break 'wait,
+                    Event::Signal(signal, SignalAction::Terminate) => {
+                        let name = signal::name(signal);
+                        log::info("shutdown", format!("Got {}; exiting...", name)).field("signal", name).emit();
+                        process::exit(0);
+                    },
+                    Event::Reap => init::reap(None, || Ok(()))?,
+                    Event::Failed(source) => {
+                        let message = format!("The {:?} event source failed during startup; exiting...", source);
+                        log::error("source_failed", message).field("source", format!("{:?}", source)).emit();
+                        process::exit(1);
+                    },
+                    _ => ()
+                }
+            }
+        }
+    }
+}
+
+
+/// Runs the daemon
+fn run() -> Result {
+    // Configure the logger first so that all subsequent messages use the configured format
+    let log_format = env::get_or("AUTOCONFD_LOG_FORMAT", Format::Text)?;
+    log::init(env::get_or("AUTOCONFD_LOG_LEVEL", Level::Info)?, log_format);
+
     // Create the config handler
-    let config_file_pattern = FileExtensionPattern::new(CONFIG_FILE_EXT);
-    let config = Config::new(CONFIG_DIR, CONFIG_FILE, config_file_pattern.clone());
+    let metrics = Metrics::new();
+    let settle_window = Duration::from_millis(env::get_or("AUTOCONFD_SETTLE_MS", SETTLE_MS)?);
+    let transactions = env::get_or("AUTOCONFD_TRANSACTIONS", TransactionMode::Off)?;
+    let symlinks = env::get_or("AUTOCONFD_SYMLINKS", SymlinkPolicy::Follow)?;
+    let admission = Admission::new(env::get_or("AUTOCONFD_ALLOW_WORLD_WRITABLE", false)?)
+        .with_allowed_uids(env::get::<IdList>("AUTOCONFD_ALLOWED_UIDS")?)
+        .with_tenants(env::get_or("AUTOCONFD_TENANTS", TenantMap::default())?);
+    let trusted_keys = env::get::<String>("AUTOCONFD_TRUSTED_KEYS")?.map(TrustedKeys::new);
+    let policy = match env::get::<String>("AUTOCONFD_POLICY")? {
+        Some(path) => Some(Policy::load(&path).context("load policy", path)?),
+        None => None
+    };
+    let limits = Limits {
+        max_fragment_size: env::get_or("AUTOCONFD_MAX_FRAGMENT_SIZE", MAX_FRAGMENT_SIZE)?,
+        max_fragments: env::get_or("AUTOCONFD_MAX_FRAGMENTS", MAX_FRAGMENTS)?,
+        max_total_size: env::get_or("AUTOCONFD_MAX_TOTAL_SIZE", MAX_TOTAL_SIZE)?,
+        action: env::get_or("AUTOCONFD_LIMIT_ACTION", LimitAction::Exclude)?
+    };
+    let inbox = Inbox::new(CONFIG_DIR, FileExtensionPattern::new(CONFIG_FILE_EXT))
+        .with_settle_window(settle_window).with_transactions(transactions).with_symlinks(symlinks)
+        .with_admission(admission).with_trusted_keys(trusted_keys).with_policy(policy)
+        .with_limits(limits).with_metrics(metrics.clone());
+    let guard = DeletionGuard {
+        max_removal_percent: env::get_or("AUTOCONFD_MAX_REMOVAL_PERCENT", MAX_REMOVAL_PERCENT)?,
+        min_fragments: env::get_or("AUTOCONFD_MIN_FRAGMENTS", MIN_FRAGMENTS)?
+    };
+    let config = Config::new(inbox.clone(), CONFIG_FILE).with_guard(guard).with_metrics(metrics.clone());
+    let mut supervisor = Supervisor::new(
+        env::get_or("AUTOCONFD_RESTART_POLICY", RestartPolicy::OnFailure)?,
+        env::get_or("AUTOCONFD_MAX_RESTARTS", MAX_RESTARTS)?,
+        Duration::from_secs(env::get_or("AUTOCONFD_RESTART_WINDOW_S", RESTART_WINDOW_S)?),
+        env::get_or("AUTOCONFD_CRASH_ACTION", CrashAction::Fallback)?
+    );
+    let drain_timeout = Duration::from_secs(env::get_or("AUTOCONFD_DRAIN_TIMEOUT_S", DRAIN_TIMEOUT_S)?);
+    let signals = env::get_or("AUTOCONFD_SIGNALS", SignalMap::default())?;
+    let init = env::get_or("AUTOCONFD_INIT", InitMode::Auto)?.is_enabled();
+    let syslog_socket = env::get::<PathBuf>("AUTOCONFD_SYSLOG_SOCKET")?;
+    let syslog_socket_mode = env::get_or("AUTOCONFD_SYSLOG_SOCKET_MODE", SYSLOG_SOCKET_MODE)?;
+    let syslog_socket_owner = env::get::<SocketOwner>("AUTOCONFD_SYSLOG_SOCKET_OWNER")?;
+    let syslog_udp = env::get::<LoopbackAddr>("AUTOCONFD_SYSLOG_UDP")?.map(|LoopbackAddr(address)| address);
+    let syslog_format = env::get_or("AUTOCONFD_SYSLOG_FORMAT", log_format)?;
+    let summary_interval = Duration::from_secs(env::get_or("AUTOCONFD_TRAFFIC_SUMMARY_S", TRAFFIC_SUMMARY_S)?);
+    let metrics_address = env::get::<SocketAddr>("AUTOCONFD_METRICS_ADDR")?;
+    let admin_socket = env::get::<PathBuf>("AUTOCONFD_ADMIN_SOCKET")?;
+    let admin_address = env::get::<LoopbackAddr>("AUTOCONFD_ADMIN_ADDR")?.map(|LoopbackAddr(address)| address);
     
-    // Assemble the config for the first time and launch HAProxy
-    config.assemble();
-    let haproxy = ChildProcess::new(HAPROXY_BIN, ["-f", CONFIG_FILE]);
+    // Trap the signals before the config is assembled so that the daemon can be stopped while it waits for the inbox
+    let mut reactor = Reactor::new()?;
+    let signal_event = |signals: SignalMap| move |signal| Event::Signal(signal, signals.action(signal));
+    let signal_event_source = SignalEventSource::new(&signals.signals())?;
+    reactor.attach(signal_event_source, signal_event(signals.clone()), Event::Failed(Source::Signal));
+    if init {
+        // Adopt and reap orphaned descendants
+        init::become_subreaper()?;
+        reactor.attach(SignalEventSource::new(&[libc::SIGCHLD])?, |_| Event::Reap, Event::Failed(Source::Reaper));
+    }
+    let restart_timer = TimerEventSource::new();
+    reactor.attach(restart_timer.clone(), |_| Event::Restart, Event::Failed(Source::Restart));
+
+    // Assemble the config for the first time
+    let assembly = assemble_initial(&config, &mut reactor, &restart_timer)?;
 
-    // Create the event sources
-    let mut directory_event_source = DirectoryEventSource::new(CONFIG_DIR, config_file_pattern.clone());
-    let mut signal_event_source = SignalEventSource::new();
-    let mut child_event_source = haproxy.event_source();
+    // Listen for syslog messages before HAProxy is launched and pass the address to HAProxy
+    let syslog = match (&syslog_socket, syslog_udp) {
+        (None, None) => None,
+        (socket, udp) => Some(SyslogEventSource::bind(socket.as_deref(), syslog_socket_mode, syslog_socket_owner, udp)?)
+    };
+    let haproxy_env: Vec<_> = syslog.iter().filter_map(|syslog| syslog.address())
+        .map(|address| (SYSLOG_ADDR_VAR.to_string(), address)).collect();
+    let haproxy = ChildProcess::new(HAPROXY_BIN, ["-f", CONFIG_FILE], haproxy_env, config.generation())?;
 
-    // Register the event sources
-    let (event_sender, event_channel) = Event::make_channels();
-    directory_event_source.event_attach(Event::Directory, event_sender.clone());
-    signal_event_source.event_attach(Event::Signal, event_sender.clone());
-    child_event_source.event_attach(Event::Child, event_sender.clone());
+    // Attach the remaining event sources
+    let baseline = Baseline::default();
+    let directory_event_source = DirectoryEventSource::new(inbox.clone(), baseline.clone());
+    reactor.attach(directory_event_source, |_| Event::Directory, Event::Failed(Source::Directory));
+    // Attach the output before the exit so that the last lines of HAProxy are relayed before its exit is handled
+    reactor.attach(haproxy.output_source(), Event::Output, Event::Failed(Source::Output));
+    reactor.attach(haproxy.event_source(), Event::Child, Event::Failed(Source::Child));
+    let drain_timer = TimerEventSource::new();
+    reactor.attach(drain_timer.clone(), |_| Event::Drain, Event::Failed(Source::Drain));
+    if let Some(syslog) = &syslog {
+        reactor.attach(syslog.clone(), Event::Syslog, Event::Failed(Source::Syslog));
+    }
+    let metrics_endpoint = metrics_address.map(|address| HttpEventSource::bind(None, Some(address))).transpose()?;
+    if let Some(metrics_endpoint) = &metrics_endpoint {
+        reactor.attach(metrics_endpoint.clone(), Event::Metrics, Event::Failed(Source::Metrics));
+    }
+    let admin_endpoint = match (&admin_socket, admin_address) {
+        (None, None) => None,
+        (socket, address) => Some(HttpEventSource::bind(socket.as_deref(), address)?)
+    };
+    if let Some(admin_endpoint) = &admin_endpoint {
+        reactor.attach(admin_endpoint.clone(), Event::Admin, Event::Failed(Source::Admin));
+    }
+    let summary_timer = TimerEventSource::new();
+    reactor.attach(summary_timer.clone(), |_| Event::Summary, Event::Failed(Source::Summary));
+    if syslog.is_some() && !summary_interval.is_zero() {
+        summary_timer.arm(Instant::now() + summary_interval);
+    }
 
     // Process incoming events
-    eprintln!("haproxy-autoconfd is up and running...");
-    for event in event_channel {
-        match event {
-            // Terminate the process
-            Event::Signal => {
-                eprintln!("Got signal; exiting...");
-                haproxy.kill();
-                process::exit(0);
-            },
-            // Handle HAProxy crash
-            Event::Child => {
-                eprintln!("HAProxy stopped unexpectedly; exiting...");
-                process::exit(1);
-            },
-            // Rebuild the config and restart HAProxy
-            Event::Directory => {
-                eprintln!("Directory changed; reloading...");
-                config.assemble();
-                haproxy.restart();
+    log::info("startup", "haproxy-autoconfd is up and running...").field("pid", haproxy.pid()?)
+        .field("generation", config.generation()).field("fragments", assembly.fragments)
+        .field("config_digest", assembly.digest).emit();
+    let (mut source_restarts, mut shutdown, mut last_exit) = (Vec::<Instant>::new(), false, None::<ChildExit>);
+    let mut traffic = Traffic::new();
+    let mut admin = Admin::new();
+    loop {
+        for event in reactor.wait()? {
+            match event {
+                // Relay the output of HAProxy (also during shutdown)
+                Event::Output(output) => {
+                    log::info("haproxy_output", format!("HAProxy {}", output)).field("pid", output.pid)
+                        .field("generation", output.generation).field("stream", output.stream.to_string()).emit();
+                },
+                // Write the syslog messages to stdout (also during shutdown) and count the requests
+                Event::Syslog(message) => {
+                    traffic.record(&message.text);
+                    let line = match syslog_format {
+                        Format::Text => message.to_string(),
+                        Format::Json => message.to_json()
+                    };
+                    let _ = writeln!(io::stdout().lock(), "{}", line);
+                },
+                // Kill HAProxy if the shutdown is forced or the drain timeout has passed
+                Event::Signal(signal, SignalAction::Terminate) if shutdown => {
+                    let name = signal::name(signal);
+                    log::warn("haproxy_kill", format!("Got {} during shutdown; killing HAProxy...", name))
+                        .field("signal", name).emit();
+                    kill(&haproxy);
+                },
+                Event::Drain => {
+                    let timeout = drain_timeout.as_secs_f32();
+                    log::warn("haproxy_kill", format!("HAProxy did not stop within {}s; killing HAProxy...", timeout))
+                        .field("drain_timeout_s", drain_timeout.as_secs_f64()).emit();
+                    kill(&haproxy);
+                },
+                // Stop HAProxy gracefully and exit once it has stopped
+                Event::Signal(signal, SignalAction::Terminate) => {
+                    shutdown = true;
+                    restart_timer.disarm();
+                    let name = signal::name(signal);
+                    match haproxy.signal(libc::SIGUSR1) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            log::info("shutdown", format!("Got {}; exiting...", name)).field("signal", name).emit();
+                            process::exit(last_exit.map_or(0, |exit| exit.code()));
+                        },
+                        // Wait for the drain timeout to kill HAProxy
+                        Err(e) => log::error("signal", format!("Failed to stop HAProxy gracefully: {}", e)).error(e).emit()
+                    }
+                    let timeout = drain_timeout.as_secs_f32();
+                    let message = format!("Got {}; stopping HAProxy gracefully (up to {}s)...", name, timeout);
+                    log::info("shutdown", message).field("signal", name)
+                        .field("drain_timeout_s", drain_timeout.as_secs_f64()).emit();
+                    drain_timer.arm(Instant::now() + drain_timeout);
+                },
+                Event::Child(exit) if shutdown => {
+                    let message = format!("HAProxy stopped: {}; exiting...", exit);
+                    exit_fields(log::info("haproxy_exit", message), &exit).emit();
+                    process::exit(exit.code());
+                },
+                // Forward or ignore signals
+                Event::Signal(signal, SignalAction::Forward) => {
+                    let name = signal::name(signal);
+                    match haproxy.signal(signal) {
+                        Ok(true) => log::debug("signal", format!("Forwarded {} to HAProxy", name)),
+                        Ok(false) => log::warn("signal", format!("Cannot forward {}: HAProxy is not running", name)),
+                        Err(e) => log::error("signal", format!("Failed to forward {} to HAProxy: {}", name, e)).error(e)
+                    }.field("signal", name).emit();
+                },
+                Event::Signal(signal, SignalAction::Ignore) => {
+                    let name = signal::name(signal);
+                    log::info("signal", format!("Ignoring {}", name)).field("signal", name).emit();
+                },
+                // Reap orphaned descendants
+                Event::Reap => {
+                    if let Err(e) = haproxy.reap() {
+                        log::error("reap", format!("Failed to reap orphaned processes: {}", e)).error(e).emit();
+                    }
+                },
+                // Serve the metrics
+                Event::Metrics(request) => {
+                    let uptime = haproxy.signal(0).and_then(|running| running.then(|| haproxy.uptime()).transpose());
+                    let uptime = match uptime {
+                        Ok(uptime) => uptime,
+                        Err(e) => {
+                            log::warn("metrics", format!("Failed to query HAProxy: {}", e)).error(e).emit();
+                            None
+                        }
+                    };
+                    metrics.serve(request, uptime, &traffic);
+                },
+                // Serve the admin API
+                Event::Admin(request) => admin.serve(request, &inbox, &config, shutdown, || {
+                    reload(&config, &haproxy, &supervisor, &restart_timer, &metrics)
+                })?,
+                // Log the traffic summary periodically
+                Event::Summary => {
+                    traffic.log_summary();
+                    summary_timer.arm(Instant::now() + summary_interval);
+                },
+                // Ignore all other events during shutdown
+                Event::Restart | Event::Directory | Event::Signal(_, SignalAction::Reload) if shutdown => (),
+                // Restart HAProxy according to the restart policy
+                Event::Child(exit) => {
+                    last_exit = Some(exit);
+                    if exit.code() != 0 {
+                        metrics.crashed();
+                        admin.record_error(format!("HAProxy stopped: {}", exit));
+                    }
+
+                    // Remember the config if HAProxy has been stable with it
+                    if supervisor.is_stable(exit.uptime) && exit.generation == config.generation() {
+                        config.mark_good();
+                    }
+
+                    // Restart HAProxy, fall back to the last-known-good config or exit
+                    match supervisor.decide(&exit, config.can_fallback()) {
+                        Decision::Restart(delay) => {
+                            let message = format!("HAProxy stopped: {}; restarting in {}s...", exit, delay.as_secs_f32());
+                            let record = exit_fields(log::warn("haproxy_exit", message), &exit);
+                            record.field("restart_delay_s", delay.as_secs_f64()).emit();
+                            restart_timer.arm(Instant::now() + delay);
+                        },
+                        Decision::Fallback => {
+                            let message = "falling back to the last-known-good config";
+                            let message = format!("!!! HAProxy is crash-looping: {}; {} !!!", exit, message);
+                            exit_fields(log::error("crash_loop", message), &exit).emit();
+                            if let Err(e) = config.fallback() {
+                                let message = format!("Failed to fall back: {}; restarting with the current config", e);
+                                log::error("fallback_failed", message).error(e).emit();
+                            }
+                            haproxy.restart(config.generation())?;
+                            metrics.restarted();
+                        },
+                        Decision::Exit => {
+                            let message = format!("HAProxy stopped: {}; exiting...", exit);
+                            exit_fields(log::error("haproxy_exit", message), &exit).emit();
+                            process::exit(exit.code());
+                        }
+                    }
+                },
+                // Restart HAProxy after the restart delay
+                Event::Restart => {
+                    haproxy.restart(config.generation())?;
+                    metrics.restarted();
+                    log::info("haproxy_restart", "Restarted HAProxy").field("pid", haproxy.pid()?)
+                        .field("generation", config.generation()).emit();
+                },
+                // Defer directory changes while watching is paused
+                Event::Directory if admin.is_paused() => admin.defer(),
+                // Rebuild the config and restart HAProxy
+                Event::Directory | Event::Signal(_, SignalAction::Reload) => {
+                    // Log the cause
+                    let record = match event {
+                        Event::Signal(signal, _) => {
+                            let name = signal::name(signal);
+                            log::info("reload", format!("Got {}; reloading...", name)).field("cause", name)
+                        },
+                        _ => log::info("reload", "Directory changed; reloading...").field("cause", "directory")
+                    };
+                    record.emit();
+                    let (outcome, message) = reload(&config, &haproxy, &supervisor, &restart_timer, &metrics)?;
+                    admin.reloaded(outcome, &message);
+                },
+                // Restart a failed event source or give up
+                Event::Failed(source) => {
+                    let now = Instant::now();
+                    source_restarts.retain(|restart| now.duration_since(*restart) < SOURCE_RESTART_WINDOW);
+                    if source_restarts.len() >= MAX_SOURCE_RESTARTS {
+                        let message = format!("The {:?} event source failed too often; exiting...", source);
+                        log::error("source_failed", message).field("source", format!("{:?}", source)).emit();
+                        haproxy.kill();
+                        process::exit(1);
+                    }
+                    source_restarts.push(now);
+
+                    // Reattach the event source
+                    let message = format!("The {:?} event source failed; restarting it...", source);
+                    admin.record_error(message.clone());
+                    log::warn("source_failed", message).field("source", format!("{:?}", source)).emit();
+                    match source {
+                        Source::Directory => {
+                            // Keep the baseline so that the changes made in the meantime are picked up
+                            let directory_event_source = DirectoryEventSource::new(inbox.clone(), baseline.clone());
+                            reactor.attach(directory_event_source, |_| Event::Directory, event)
+                        },
+                        Source::Signal => {
+                            let signal_event_source = SignalEventSource::new(&signals.signals())?;
+                            reactor.attach(signal_event_source, signal_event(signals.clone()), event)
+                        },
+                        Source::Child => reactor.attach(haproxy.event_source(), Event::Child, event),
+                        Source::Output => reactor.attach(haproxy.output_source(), Event::Output, event),
+                        Source::Restart => reactor.attach(restart_timer.clone(), |_| Event::Restart, event),
+                        Source::Drain => reactor.attach(drain_timer.clone(), |_| Event::Drain, event),
+                        Source::Reaper => {
+                            let reaper_event_source = SignalEventSource::new(&[libc::SIGCHLD])?;
+                            reactor.attach(reaper_event_source, |_| Event::Reap, event)
+                        },
+                        Source::Syslog => {
+                            if let Some(syslog) = &syslog {
+                                reactor.attach(syslog.clone(), Event::Syslog, event)
+                            }
+                        },
+                        Source::Summary => reactor.attach(summary_timer.clone(), |_| Event::Summary, event),
+                        Source::Metrics => {
+                            if let Some(metrics_endpoint) = &metrics_endpoint {
+                                reactor.attach(metrics_endpoint.clone(), Event::Metrics, event)
+                            }
+                        },
+                        Source::Admin => {
+                            if let Some(admin_endpoint) = &admin_endpoint {
+                                reactor.attach(admin_endpoint.clone(), Event::Admin, event)
+                            }
+                        }
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,322 @@
+use crate::{
+    events::http::Request,
+    traffic::{ Series, StatusClass, Traffic, LATENCY_BUCKETS_MS }
+};
+use std::{
+    fmt::Write,
+    sync::{ Arc, Mutex, MutexGuard, PoisonError },
+    time::{ Duration, SystemTime, UNIX_EPOCH }
+};
+
+
+/// The upper bounds of the duration histogram buckets in seconds
+const DURATION_BUCKETS_S: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+
+/// The outcome of a reload attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reload {
+    /// The config has been applied
+    Success,
+    /// The fragments have been refused and the current config has been kept
+    Refused,
+    /// The config could not be assembled
+    Failure,
+    /// The inbox is in the middle of a transaction and the current config has been kept
+    Deferred
+}
+
+
+/// A histogram of durations
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// The amount of observations per bucket (indexed like [`DURATION_BUCKETS_S`] plus `+Inf`)
+    buckets: [u64; DURATION_BUCKETS_S.len() + 1],
+    /// The sum of all observations in seconds
+    sum: f64
+}
+impl Histogram {
+    /// Records `duration`
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS_S.iter().position(|bound| seconds <= *bound).unwrap_or(DURATION_BUCKETS_S.len());
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+    }
+}
+
+
+/// The metric values
+#[derive(Debug, Clone, Default)]
+struct State {
+    /// The amount of reload attempts
+    reload_attempts: u64,
+    /// The amount of successful reloads
+    reload_successes: u64,
+    /// The amount of failed reloads (including refused ones)
+    reload_failures: u64,
+    /// The amount of refused changes and rejected fragments
+    validation_errors: u64,
+    /// The point in time of the last successful reload (if any)
+    last_reload: Option<SystemTime>,
+    /// The durations of the config assemblies
+    assembly_durations: Histogram,
+    /// The durations of the inbox scans
+    scan_durations: Histogram,
+    /// The amount of fragments within the current config
+    fragments: usize,
+    /// The size of the current config in bytes
+    config_bytes: usize,
+    /// The amount of HAProxy restarts after an exit
+    restarts: u64,
+    /// The amount of HAProxy crashes (i.e. exits with a non-zero code or a signal)
+    crashes: u64
+}
+
+
+/// The daemon's metrics
+///
+/// # Note
+/// All clones share the same metric values.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// The metric values (shared between all clones)
+    state: Arc<Mutex<State>>
+}
+impl Metrics {
+    /// Creates a new metrics registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a reload attempt with the given `outcome`
+    pub fn reload(&self, outcome: Reload) {
+        let mut state = self.state();
+        state.reload_attempts += 1;
+        match outcome {
+            Reload::Success => {
+                state.reload_successes += 1;
+                state.last_reload = Some(SystemTime::now());
+            },
+            Reload::Refused => {
+                state.reload_failures += 1;
+                state.validation_errors += 1;
+            },
+            Reload::Failure => state.reload_failures += 1,
+            Reload::Deferred => ()
+        }
+    }
+    /// Records `count` newly rejected fragments
+    pub fn rejected(&self, count: usize) {
+        self.state().validation_errors += count as u64;
+    }
+    /// Records a config assembly that took `duration` and resulted in `fragments` fragments and `bytes` bytes
+    pub fn assembled(&self, duration: Duration, fragments: usize, bytes: usize) {
+        let mut state = self.state();
+        state.assembly_durations.observe(duration);
+        (state.fragments, state.config_bytes) = (fragments, bytes);
+    }
+    /// Records an inbox scan that took `duration`
+    pub fn scanned(&self, duration: Duration) {
+        self.state().scan_durations.observe(duration);
+    }
+    /// Records a HAProxy restart after an exit
+    pub fn restarted(&self) {
+        self.state().restarts += 1;
+    }
+    /// Records a HAProxy crash
+    pub fn crashed(&self) {
+        self.state().crashes += 1;
+    }
+
+    /// Renders the metrics in the Prometheus text format, together with the current HAProxy `uptime` and the `traffic`
+    /// counters
+    pub fn render(&self, uptime: Option<Duration>, traffic: &Traffic) -> String {
+        let state = self.state();
+        let mut out = String::new();
+
+        // Render the daemon metrics
+        let counters = [
+            ("reload_attempts_total", "The amount of reload attempts", state.reload_attempts),
+            ("reload_successes_total", "The amount of successful reloads", state.reload_successes),
+            ("reload_failures_total", "The amount of failed or refused reloads", state.reload_failures),
+            ("validation_errors_total", "The amount of refused changes and rejected fragments", state.validation_errors),
+            ("haproxy_restarts_total", "The amount of HAProxy restarts after an exit", state.restarts),
+            ("haproxy_crashes_total", "The amount of HAProxy exits with a non-zero code or a signal", state.crashes)
+        ];
+        for (name, help, value) in counters {
+            header(&mut out, name, help, "counter");
+            let _ = writeln!(out, "haproxy_autoconfd_{} {}", name, value);
+        }
+        let last_reload = state.last_reload.and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+        let gauges = [
+            ("last_reload_success_timestamp_seconds", "The time of the last successful reload",
+                last_reload.map(|time| time.as_secs_f64())),
+            ("fragments", "The amount of fragments within the current config", Some(state.fragments as f64)),
+            ("config_bytes", "The size of the current config in bytes", Some(state.config_bytes as f64)),
+            ("haproxy_uptime_seconds", "The time the current HAProxy process has been running",
+                uptime.map(|uptime| uptime.as_secs_f64()))
+        ];
+        for (name, help, value) in gauges {
+            header(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "haproxy_autoconfd_{} {}", name, value.unwrap_or_default());
+        }
+        let histograms = [
+            ("assembly_duration_seconds", "The time it took to assemble the config", &state.assembly_durations),
+            ("scan_duration_seconds", "The time it took to scan the inbox", &state.scan_durations)
+        ];
+        for (name, help, histogram) in histograms {
+            header(&mut out, name, help, "histogram");
+            let bounds = DURATION_BUCKETS_S.iter().map(|bound| bound.to_string());
+            buckets(&mut out, name, "", bounds, &histogram.buckets);
+            let count: u64 = histogram.buckets.iter().sum();
+            let _ = writeln!(out, "haproxy_autoconfd_{}_sum {}", name, histogram.sum);
+            let _ = writeln!(out, "haproxy_autoconfd_{}_count {}", name, count);
+        }
+
+        // Render the traffic metrics
+        header(&mut out, "traffic_requests_total", "The amount of requests per status class", "counter");
+        for (series, counters) in traffic.series() {
+            for class in StatusClass::ALL {
+                let labels = format!("{},class=\"{}\"", series_labels(series), class);
+                let _ = writeln!(out, "haproxy_autoconfd_traffic_requests_total{{{}}} {}", labels, counters.status(class));
+            }
+        }
+        header(&mut out, "traffic_bytes_total", "The amount of bytes sent to the clients", "counter");
+        for (series, counters) in traffic.series() {
+            let _ = writeln!(out, "haproxy_autoconfd_traffic_bytes_total{{{}}} {}", series_labels(series), counters.bytes);
+        }
+        let name = "traffic_request_duration_seconds";
+        header(&mut out, name, "The total time of completed requests", "histogram");
+        for (series, counters) in traffic.series() {
+            let labels = series_labels(series);
+            let bounds = LATENCY_BUCKETS_MS.iter().map(|bound| (*bound as f64 / 1000.0).to_string());
+            buckets(&mut out, name, &labels, bounds, &counters.latency_buckets);
+            let sum = counters.latency_sum_ms as f64 / 1000.0;
+            let _ = writeln!(out, "haproxy_autoconfd_{}_sum{{{}}} {}", name, labels, sum);
+            let _ = writeln!(out, "haproxy_autoconfd_{}_count{{{}}} {}", name, labels, counters.completed());
+        }
+        header(&mut out, "traffic_dropped_total", "The amount of requests that exceeded the series limit", "counter");
+        let _ = writeln!(out, "haproxy_autoconfd_traffic_dropped_total {}", traffic.dropped());
+        out
+    }
+
+    /// Serves a `request` to the metrics endpoint with the current HAProxy `uptime` and the `traffic` counters
+    pub fn serve(&self, request: Request, uptime: Option<Duration>, traffic: &Traffic) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                let body = self.render(uptime, traffic);
+                request.respond(200, "text/plain; version=0.0.4", body.as_bytes());
+            },
+            (_, "/metrics") => request.respond(405, "text/plain", b"Method not allowed\n"),
+            _ => request.respond(404, "text/plain", b"Not found\n")
+        }
+    }
+
+    /// Locks the metric values
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+/// Writes the `HELP` and `TYPE` lines of the metric `name`
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP haproxy_autoconfd_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE haproxy_autoconfd_{} {}", name, kind);
+}
+
+
+/// Writes the cumulative histogram buckets of the metric `name` with the additional `labels`
+fn buckets<B>(out: &mut String, name: &str, labels: &str, bounds: B, counts: &[u64]) where B: Iterator<Item = String> {
+    let separator = if labels.is_empty() { "" } else { "," };
+    let mut cumulative = 0;
+    for (bound, count) in bounds.chain(Some("+Inf".to_string())).zip(counts) {
+        cumulative += count;
+        let _ = writeln!(out, "haproxy_autoconfd_{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative);
+    }
+}
+
+
+/// Formats the labels of a traffic series
+fn series_labels(series: &Series) -> String {
+    format!(
+        "frontend=\"{}\",backend=\"{}\",server=\"{}\"",
+        escape(&series.frontend), escape(&series.backend), escape(&series.server)
+    )
+}
+
+
+/// Escapes a label value
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the value of the sample `name` (including its labels) from the rendered `metrics`
+    fn sample<'a>(metrics: &'a str, name: &str) -> Option<&'a str> {
+        metrics.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+    }
+
+    #[test]
+    fn reload_outcomes() {
+        let metrics = Metrics::new();
+        for outcome in [Reload::Success, Reload::Refused, Reload::Failure, Reload::Deferred] {
+            metrics.reload(outcome);
+        }
+        metrics.rejected(2);
+        metrics.restarted();
+        metrics.crashed();
+
+        let rendered = metrics.render(None, &Traffic::new());
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_reload_attempts_total"), Some("4"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_reload_successes_total"), Some("1"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_reload_failures_total"), Some("2"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_validation_errors_total"), Some("3"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_haproxy_restarts_total"), Some("1"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_haproxy_crashes_total"), Some("1"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_haproxy_uptime_seconds"), Some("0"));
+        assert_ne!(sample(&rendered, "haproxy_autoconfd_last_reload_success_timestamp_seconds"), Some("0"));
+        assert!(rendered.contains("# TYPE haproxy_autoconfd_reload_attempts_total counter\n"));
+    }
+
+    #[test]
+    fn histograms() {
+        let metrics = Metrics::new();
+        metrics.assembled(Duration::from_millis(3), 4, 1024);
+        metrics.assembled(Duration::from_secs(10), 5, 2048);
+
+        let rendered = metrics.render(Some(Duration::from_millis(1500)), &Traffic::new());
+        let name = "haproxy_autoconfd_assembly_duration_seconds";
+        assert_eq!(sample(&rendered, &format!("{}_bucket{{le=\"0.001\"}}", name)), Some("0"));
+        assert_eq!(sample(&rendered, &format!("{}_bucket{{le=\"0.005\"}}", name)), Some("1"));
+        assert_eq!(sample(&rendered, &format!("{}_bucket{{le=\"5\"}}", name)), Some("1"));
+        assert_eq!(sample(&rendered, &format!("{}_bucket{{le=\"+Inf\"}}", name)), Some("2"));
+        assert_eq!(sample(&rendered, &format!("{}_sum", name)), Some("10.003"));
+        assert_eq!(sample(&rendered, &format!("{}_count", name)), Some("2"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_fragments"), Some("5"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_config_bytes"), Some("2048"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_haproxy_uptime_seconds"), Some("1.5"));
+    }
+
+    #[test]
+    fn traffic_series() {
+        let mut traffic = Traffic::new();
+        let line = "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe be\"x/srv1 0/0/1/2/30 404 512 - - ---- \"GET / HTTP/1.1\"";
+        traffic.record(line);
+
+        let rendered = Metrics::new().render(None, &traffic);
+        let labels = r#"frontend="fe",backend="be\"x",server="srv1""#;
+        let requests = |class: &str| format!("haproxy_autoconfd_traffic_requests_total{{{},class=\"{}\"}}", labels, class);
+        assert_eq!(sample(&rendered, &requests("4xx")), Some("1"));
+        assert_eq!(sample(&rendered, &requests("2xx")), Some("0"));
+        assert_eq!(sample(&rendered, &format!("haproxy_autoconfd_traffic_bytes_total{{{}}}", labels)), Some("512"));
+        let bucket = format!("haproxy_autoconfd_traffic_request_duration_seconds_bucket{{{},le=\"0.05\"}}", labels);
+        assert_eq!(sample(&rendered, &bucket), Some("1"));
+        assert_eq!(sample(&rendered, "haproxy_autoconfd_traffic_dropped_total"), Some("0"));
+    }
+}
@@ -0,0 +1,317 @@
+use crate::inbox::Fragment;
+use std::{ fs, io::{ Error, ErrorKind, Result }, path::{ Path, PathBuf } };
+
+
+/// The HAProxy section keywords
+const SECTIONS: &[&str] = &[
+    "global", "defaults", "frontend", "backend", "listen", "userlist", "peers", "resolvers", "mailers", "program",
+    "http-errors", "ring", "cache", "fcgi-app", "crt-store", "traces", "log-forward"
+];
+/// The pseudo-section name for lines before the first section
+const NO_SECTION: &str = "none";
+/// The source name for fragments without a tenant
+const NO_TENANT: &str = "-";
+/// The wildcard
+const ANY: &str = "*";
+
+
+/// A single policy rule
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Whether the rule allows or denies matching lines
+    allow: bool,
+    /// The source (tenant name, `-` for fragments without tenant or `*`)
+    source: String,
+    /// The section type (or `*`)
+    section: String,
+    /// The leading words of the directive (or `*`)
+    directive: Vec<String>
+}
+impl Rule {
+    /// Parses a rule line (`allow|deny SOURCE SECTION DIRECTIVE...`)
+    pub fn parse(line: &str) -> std::result::Result<Option<Self>, String> {
+        // Skip empty lines and comments
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(action) = words.next() else {
+            return Ok(None);
+        };
+
+        // Parse the fields
+        let allow = match action {
+            "allow" => true,
+            "deny" => false,
+            action => return Err(format!("invalid action {} (expected allow or deny)", action))
+        };
+        let (Some(source), Some(section)) = (words.next(), words.next()) else {
+            return Err("missing source or section".to_string());
+        };
+        let directive: Vec<_> = words.map(str::to_string).collect();
+        if directive.is_empty() {
+            return Err("missing directive".to_string());
+        }
+        Ok(Some(Self { allow, source: source.to_string(), section: section.to_string(), directive }))
+    }
+
+    /// Tests whether the rule applies to a line
+    pub fn matches(&self, source: &str, section: &str, words: &[&str]) -> bool {
+        let source_matches = self.source == ANY || self.source == source;
+        let section_matches = self.section == ANY || self.section == section;
+        let directive_matches = self.directive == [ANY]
+            || (words.len() >= self.directive.len() && self.directive.iter().zip(words).all(|(a, b)| a == b));
+        source_matches && section_matches && directive_matches
+    }
+}
+
+
+/// A policy that restricts which sections and directives fragments may contain
+///
+/// # Format
+/// Each line contains a rule `allow|deny SOURCE SECTION DIRECTIVE...`. `SOURCE` is a tenant name, `-` for fragments
+/// without a tenant or `*`; `SECTION` is the section type (e.g. `backend`, `none` for lines before the first section or
+/// `*`); `DIRECTIVE` are the leading words of a config line (e.g. `stats socket`; section headers are directives within
+/// their own section type) or `*`. The first matching rule wins; lines that do not match any rule are denied.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// The rules in order of precedence
+    rules: Vec<Rule>
+}
+impl Policy {
+    /// Loads a policy file
+    pub fn load<F>(path: F) -> Result<Self> where F: AsRef<Path> {
+        let mut rules = Vec::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let rule = Rule::parse(line).map_err(|e| {
+                Error::new(ErrorKind::InvalidData, format!("Invalid policy rule at line {}: {}", index + 1, e))
+            })?;
+            rules.extend(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Enforces the policy on the ordered fragments and returns the accepted fragments; violations are reported in
+    /// `rejections` together with the fragment path
+    ///
+    /// # Note
+    /// Since the fragments are concatenated, lines before the first section header of a fragment belong to the last
+    /// section of the preceding accepted fragment.
+    pub fn enforce(&self, fragments: Vec<Fragment>, rejections: &mut Vec<(PathBuf, String)>) -> Vec<Fragment> {
+        let mut section = NO_SECTION.to_string();
+        let mut accepted = Vec::new();
+        for fragment in fragments {
+            match self.check(&fragment, &section) {
+                Ok(last_section) => {
+                    section = last_section;
+                    accepted.push(fragment);
+                },
+                Err(e) => rejections.push((fragment.path, e))
+            }
+        }
+        accepted
+    }
+
+    /// Checks a fragment starting within `section` and returns the last section of the fragment
+    fn check(&self, fragment: &Fragment, section: &str) -> std::result::Result<String, String> {
+        let source = fragment.tenant.as_deref().unwrap_or(NO_TENANT);
+        let mut section = section.to_string();
+        for (index, line) in String::from_utf8_lossy(&fragment.data).lines().enumerate() {
+            // Split the line like HAProxy and skip empty lines and comments
+            let words = words(line).map_err(|e| format!("line {} cannot be checked: {}: {}", index + 1, e, line.trim()))?;
+            let words: Vec<_> = words.iter().map(String::as_str).collect();
+            let Some(first) = words.first() else {
+                continue;
+            };
+
+            // Track the section and check the line
+            if SECTIONS.contains(first) {
+                section = first.to_string();
+            }
+            let allowed = self.rules.iter().find(|rule| rule.matches(source, &section, &words))
+                .map(|rule| rule.allow).unwrap_or(false);
+            if !allowed {
+                return Err(format!("line {} is not allowed in section {}: {}", index + 1, section, line.trim()));
+            }
+        }
+        Ok(section)
+    }
+}
+
+
+/// Splits a config line into words like HAProxy does, i.e. resolves quotes and backslash escapes and strips comments
+///
+/// # Note
+/// Environment variables within double quotes are expanded by HAProxy at startup, so their value cannot be checked and
+/// such lines are refused.
+fn words(line: &str) -> std::result::Result<Vec<String>, String> {
+    let (mut words, mut word) = (Vec::new(), None::<Vec<u8>>);
+    let (mut single, mut double) = (false, false);
+    let mut bytes = line.bytes().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' if !single => {
+                let escaped = match bytes.next() {
+                    Some(b'r') => b'\r',
+                    Some(b'n') => b'\n',
+                    Some(b't') => b'\t',
+                    Some(b'x') => {
+                        let hex = [bytes.next().unwrap_or_default(), bytes.next().unwrap_or_default()];
+                        let hex = std::str::from_utf8(&hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                        hex.ok_or("invalid hex escape")?
+                    },
+                    Some(escaped) => escaped,
+                    None => b'\\'
+                };
+                word.get_or_insert_with(Vec::new).push(escaped);
+            },
+            b'\'' if !double => {
+                single = !single;
+                word.get_or_insert_with(Vec::new);
+            },
+            b'"' if !single => {
+                double = !double;
+                word.get_or_insert_with(Vec::new);
+            },
+            b'$' if double && matches!(bytes.peek(), Some(b'{' | b'_' | b'a'..=b'z' | b'A'..=b'Z')) => {
+                return Err("environment variables cannot be checked".to_string());
+            },
+            b'#' if !single && !double => break,
+            b' ' | b'\t' if !single && !double => words.extend(word.take()),
+            byte => word.get_or_insert_with(Vec::new).push(byte)
+        }
+    }
+    if single || double {
+        return Err("unterminated quote".to_string());
+    }
+    words.extend(word);
+    Ok(words.into_iter().map(|word| String::from_utf8_lossy(&word).into_owned()).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a policy from rule lines
+    fn policy(rules: &str) -> Policy {
+        let rules = rules.lines().map(|line| Rule::parse(line).expect("invalid rule"));
+        Policy { rules: rules.flatten().collect() }
+    }
+
+    /// Creates a fragment
+    fn fragment(name: &str, tenant: Option<&str>, data: &str) -> Fragment {
+        Fragment { path: PathBuf::from(name), tenant: tenant.map(str::to_string), data: data.as_bytes().to_vec() }
+    }
+
+    /// Enforces `policy` and returns the paths of the accepted fragments and the rejections
+    fn enforce(policy: &Policy, fragments: Vec<Fragment>) -> (Vec<PathBuf>, Vec<(PathBuf, String)>) {
+        let mut rejections = Vec::new();
+        let accepted = policy.enforce(fragments, &mut rejections);
+        (accepted.into_iter().map(|fragment| fragment.path).collect(), rejections)
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert!(Rule::parse("  # comment").expect("invalid rule").is_none());
+        let rule = Rule::parse("deny a backend stats socket # no sockets").expect("invalid rule").expect("no rule");
+        assert!(!rule.allow);
+        assert_eq!((rule.source.as_str(), rule.section.as_str()), ("a", "backend"));
+        assert_eq!(rule.directive, ["stats", "socket"]);
+        assert!(rule.matches("a", "backend", &["stats", "socket", "/run/sock"]));
+        assert!(!rule.matches("a", "backend", &["stats"]));
+        assert!(!rule.matches("b", "backend", &["stats", "socket"]));
+
+        assert!(Rule::parse("permit * * *").is_err());
+        assert!(Rule::parse("allow *").is_err());
+        assert!(Rule::parse("allow * backend").is_err());
+    }
+
+    #[test]
+    fn section_carries_over_fragments() {
+        let policy = policy("allow * backend *\nallow - * *\ndeny * * *");
+        let (accepted, rejections) = enforce(&policy, vec![
+            fragment("000-base.cfg", None, "global\n    daemon\nbackend base\n"),
+            // Lines before the first section header belong to the backend of the preceding fragment
+            fragment("100-a.cfg", Some("a"), "    # comment\n\n    server s1 10.0.0.1:80\n")
+        ]);
+        assert_eq!(accepted, [PathBuf::from("000-base.cfg"), PathBuf::from("100-a.cfg")]);
+        assert!(rejections.is_empty());
+    }
+
+    #[test]
+    fn section_carries_over_from_last_accepted_fragment() {
+        let policy = policy("allow * backend *\nallow - * *\nallow a frontend *\ndeny * * *");
+        let (accepted, rejections) = enforce(&policy, vec![
+            fragment("000-base.cfg", None, "defaults\n    mode http\n"),
+            // The frontend section carries over into the next fragment
+            fragment("100-a.cfg", Some("a"), "frontend fe\n    bind :80\n"),
+            fragment("200-b.cfg", Some("b"), "    timeout client 1s\n"),
+            fragment("300-c.cfg", Some("c"), "backend be\n"),
+            fragment("400-d.cfg", Some("d"), "    server s1 10.0.0.1:80\n")
+        ]);
+        assert_eq!(accepted, [PathBuf::from("000-base.cfg"), PathBuf::from("100-a.cfg"), PathBuf::from("300-c.cfg"),
+            PathBuf::from("400-d.cfg")]);
+        assert_eq!(rejections, [(
+            PathBuf::from("200-b.cfg"), "line 1 is not allowed in section frontend: timeout client 1s".to_string()
+        )]);
+    }
+
+    #[test]
+    fn rejected_fragments_do_not_carry_over() {
+        let policy = policy("allow - * *\nallow a frontend *\nallow a backend *\ndeny * * *");
+        let (accepted, rejections) = enforce(&policy, vec![
+            fragment("000-base.cfg", None, "frontend fe\n"),
+            fragment("100-a.cfg", Some("a"), "backend be\n    server s1 10.0.0.1:80\n    stats enable\n    listen x\n"),
+            fragment("200-a.cfg", Some("a"), "    bind :80\n")
+        ]);
+        assert_eq!(accepted, [PathBuf::from("000-base.cfg"), PathBuf::from("200-a.cfg")]);
+        assert_eq!(rejections, [(
+            PathBuf::from("100-a.cfg"), "line 4 is not allowed in section listen: listen x".to_string()
+        )]);
+    }
+
+    #[test]
+    fn split_words() {
+        assert_eq!(words("  server s1 10.0.0.1:80 # comment").unwrap(), ["server", "s1", "10.0.0.1:80"]);
+        assert_eq!(words(r#"http-request set-header X-A "a b" 'c "d"'"#).unwrap(),
+            ["http-request", "set-header", "X-A", "a b", "c \"d\""]);
+        assert_eq!(words(r"a\ b \x67lobal '\x67' \#c \").unwrap(), ["a b", "global", "\\x67", "#c", "\\"]);
+        assert_eq!(words(r#"x "" '#'"#).unwrap(), ["x", "", "#"]);
+        assert!(words("# comment").unwrap().is_empty());
+        assert!(words(r#"a "b"#).is_err());
+        assert!(words(r"\xzz").is_err());
+        assert!(words(r#"server s1 "${HOST}":80"#).is_err());
+        assert_eq!(words("server s1 ${HOST}:80 '$HOST'").unwrap(), ["server", "s1", "${HOST}:80", "$HOST"]);
+    }
+
+    #[test]
+    fn quoted_and_escaped_keywords_are_resolved() {
+        let policy = policy("allow - * *\ndeny * * lua-load\ndeny * * stats socket\nallow * backend *\ndeny * * *");
+        for (name, data) in [
+            ("100-a.cfg", "    \"global\"\n    daemon\n"),
+            ("101-a.cfg", "    \\lua-load /tmp/evil.lua\n"),
+            ("102-a.cfg", "    \\x67lobal\n"),
+            ("103-a.cfg", "    'lua-load' /tmp/evil.lua\n"),
+            ("104-a.cfg", "    stats \"socket\" /tmp/admin.sock\n"),
+            ("105-a.cfg", "    \"${SECTION}\"\n")
+        ] {
+            let (accepted, rejections) =
+                enforce(&policy, vec![fragment("000-base.cfg", None, "backend be\n"), fragment(name, Some("a"), data)]);
+            assert_eq!(accepted, [PathBuf::from("000-base.cfg")], "{:?} should be rejected", data);
+            assert_eq!(rejections.len(), 1);
+        }
+
+        // Quoted words that resolve to allowed directives are accepted
+        let fragments =
+            vec![fragment("000-base.cfg", None, "backend be\n"), fragment("100-a.cfg", Some("a"), "\"server\" s1\n")];
+        assert_eq!(enforce(&policy, fragments).1, []);
+    }
+
+    #[test]
+    fn unmatched_lines_are_denied() {
+        // Section headers are directives within their own section type
+        let policy = policy("allow * global global");
+        let (accepted, rejections) = enforce(&policy, vec![fragment("000-base.cfg", None, "global\n    daemon\n")]);
+        assert!(accepted.is_empty());
+        assert_eq!(rejections[0].1, "line 2 is not allowed in section global: daemon");
+    }
+}
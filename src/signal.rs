@@ -0,0 +1,139 @@
+use std::str::FromStr;
+
+
+/// The names of the signals that can be configured
+const NAMES: &[(&str, libc::c_int)] = &[
+    ("HUP", libc::SIGHUP), ("INT", libc::SIGINT), ("QUIT", libc::SIGQUIT), ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2), ("PIPE", libc::SIGPIPE), ("ALRM", libc::SIGALRM), ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT), ("TSTP", libc::SIGTSTP), ("TTIN", libc::SIGTTIN), ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG), ("XCPU", libc::SIGXCPU), ("XFSZ", libc::SIGXFSZ), ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF), ("WINCH", libc::SIGWINCH), ("IO", libc::SIGIO), ("PWR", libc::SIGPWR)
+];
+
+
+/// Gets the name of `signal` (e.g. `SIGHUP`)
+pub fn name(signal: libc::c_int) -> String {
+    match NAMES.iter().find(|(_, number)| *number == signal) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => format!("signal {}", signal)
+    }
+}
+
+
+/// Parses a signal name (e.g. `HUP` or `SIGHUP`) or number
+fn parse(s: &str) -> Result<libc::c_int, String> {
+    let name = s.strip_prefix("SIG").unwrap_or(s);
+    let signal = NAMES.iter().find(|(known, _)| known.eq_ignore_ascii_case(name)).map(|(_, number)| *number)
+        .or_else(|| name.parse().ok());
+    match signal {
+        Some(signal) if NAMES.iter().any(|(_, number)| *number == signal) => Ok(signal),
+        Some(signal) if (libc::SIGRTMIN()..=libc::SIGRTMAX()).contains(&signal) => Ok(signal),
+        _ => Err(format!("Invalid or untrappable signal: {}", s))
+    }
+}
+
+
+/// What to do if a signal arrives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Stop HAProxy gracefully and exit
+    Terminate,
+    /// Reassemble the config and reload HAProxy even if the inbox has not changed
+    Reload,
+    /// Forward the signal to HAProxy as-is
+    Forward,
+    /// Ignore the signal
+    Ignore
+}
+impl FromStr for SignalAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminate" => Ok(Self::Terminate),
+            "reload" => Ok(Self::Reload),
+            "forward" => Ok(Self::Forward),
+            "ignore" => Ok(Self::Ignore),
+            s => Err(format!("Invalid signal action: {} (expected terminate, reload, forward or ignore)", s))
+        }
+    }
+}
+
+
+/// A comma-separated mapping from signals to actions (e.g. `HUP=reload,WINCH=forward`) on top of the default mapping
+#[derive(Debug, Clone)]
+pub struct SignalMap(pub Vec<(libc::c_int, SignalAction)>);
+impl SignalMap {
+    /// All signals that have an action
+    pub fn signals(&self) -> Vec<libc::c_int> {
+        self.0.iter().map(|(signal, _)| *signal).collect()
+    }
+    /// The action for `signal`
+    pub fn action(&self, signal: libc::c_int) -> SignalAction {
+        let action = self.0.iter().find(|(known, _)| *known == signal).map(|(_, action)| *action);
+        action.unwrap_or(SignalAction::Ignore)
+    }
+}
+impl FromStr for SignalMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Self(mut entries) = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            // Parse the entry
+            let (signal, action) = entry.split_once('=')
+                .ok_or_else(|| format!("Invalid signal mapping: {} (expected SIGNAL=ACTION)", entry))?;
+            let (signal, action) = (parse(signal.trim())?, action.trim().parse()?);
+
+            // Replace the default action
+            entries.retain(|(known, _)| *known != signal);
+            entries.push((signal, action));
+        }
+        Ok(Self(entries))
+    }
+}
+impl Default for SignalMap {
+    fn default() -> Self {
+        Self(vec![
+            (libc::SIGTERM, SignalAction::Terminate), (libc::SIGINT, SignalAction::Terminate),
+            (libc::SIGQUIT, SignalAction::Terminate), (libc::SIGHUP, SignalAction::Reload),
+            (libc::SIGUSR1, SignalAction::Forward), (libc::SIGUSR2, SignalAction::Forward)
+        ])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(parse("HUP"), Ok(libc::SIGHUP));
+        assert_eq!(parse("SIGwinch"), Ok(libc::SIGWINCH));
+        assert_eq!(parse("15"), Ok(libc::SIGTERM));
+        assert_eq!(parse(&libc::SIGRTMIN().to_string()), Ok(libc::SIGRTMIN()));
+        assert!(parse("KILL").is_err());
+        assert!(parse("9").is_err());
+        assert!(parse("BOGUS").is_err());
+
+        assert_eq!(name(libc::SIGHUP), "SIGHUP");
+        assert_eq!(name(libc::SIGKILL), "signal 9");
+    }
+
+    #[test]
+    fn signal_maps() {
+        let map: SignalMap = "HUP=forward, SIGWINCH=reload,,TERM=ignore".parse().expect("failed to parse signal map");
+        assert_eq!(map.action(libc::SIGHUP), SignalAction::Forward);
+        assert_eq!(map.action(libc::SIGWINCH), SignalAction::Reload);
+        assert_eq!(map.action(libc::SIGTERM), SignalAction::Ignore);
+        assert_eq!(map.action(libc::SIGINT), SignalAction::Terminate);
+        assert_eq!(map.action(libc::SIGPIPE), SignalAction::Ignore);
+        assert_eq!(map.signals().iter().filter(|signal| **signal == libc::SIGHUP).count(), 1);
+
+        assert!("HUP".parse::<SignalMap>().is_err());
+        assert!("HUP=restart".parse::<SignalMap>().is_err());
+        assert!("KILL=ignore".parse::<SignalMap>().is_err());
+        assert_eq!("".parse::<SignalMap>().expect("failed to parse signal map").signals(), SignalMap::default().signals());
+    }
+}
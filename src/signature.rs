@@ -0,0 +1,191 @@
+use crate::fsext;
+use ed25519_dalek::{ Signature, VerifyingKey, pkcs8::DecodePublicKey };
+use std::{ fs, path::{ Path, PathBuf } };
+
+
+/// The file extension for detached signatures
+pub const SIGNATURE_EXT: &str = ".sig";
+
+
+/// Decodes a hex string (surrounding whitespace is ignored)
+fn decode_hex(data: &[u8]) -> Option<Vec<u8>> {
+    let data = data.trim_ascii();
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+
+    // Decode the nibbles
+    let nibble = |c: u8| (c as char).to_digit(16).map(|n| n as u8);
+    data.chunks(2).map(|pair| Some((nibble(pair[0])? << 4) | nibble(pair[1])?)).collect()
+}
+
+
+/// A set of trusted public keys
+#[derive(Debug, Clone)]
+pub struct KeySet {
+    /// The keys
+    keys: Vec<VerifyingKey>
+}
+impl KeySet {
+    /// Verifies a detached signature over `data` made by any of the keys
+    ///
+    /// # Note
+    /// The signature may be raw (64 bytes) or hex encoded.
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> Result<(), String> {
+        // Decode the signature
+        let signature = match signature.len() {
+            64 => signature.to_vec(),
+            _ => decode_hex(signature).ok_or("signature is neither raw nor hex encoded")?
+        };
+        let signature = Signature::from_slice(&signature).map_err(|_| "invalid signature length")?;
+
+        // Find a key that made the signature
+        match self.keys.iter().any(|key| key.verify_strict(data, &signature).is_ok()) {
+            true => Ok(()),
+            false => Err("signature is invalid or was not made by a trusted key".to_string())
+        }
+    }
+}
+
+
+/// A directory containing trusted Ed25519 public keys
+#[derive(Debug, Clone)]
+pub struct TrustedKeys {
+    /// The directory containing the public key files
+    directory: PathBuf
+}
+impl TrustedKeys {
+    /// Creates a new trusted keys directory handle
+    pub fn new<D>(directory: D) -> Self where D: Into<PathBuf> {
+        Self { directory: directory.into() }
+    }
+
+    /// The directory containing the public key files
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Loads all public keys from the directory; invalid key files are reported in `rejections`
+    ///
+    /// # Note
+    /// A key file may contain the raw key (32 bytes), the hex encoded key or a PEM encoded `PUBLIC KEY`. Since the keys are
+    /// reloaded on every scan, keys can be rotated by simply adding or removing key files.
+    pub fn load(&self, rejections: &mut Vec<String>) -> KeySet {
+        // List the key files
+        let mut files = match fsext::list_files(&self.directory) {
+            Ok(files) => files,
+            Err(e) => {
+                rejections.push(format!("Failed to list trusted keys in {}: {}", self.directory.display(), e));
+                Vec::new()
+            }
+        };
+        files.sort();
+
+        // Load the keys
+        let mut keys = Vec::new();
+        for path in files {
+            let key = fs::read(&path).map_err(|e| e.to_string()).and_then(|data| Self::parse(&data));
+            match key {
+                Ok(key) => keys.push(key),
+                Err(e) => rejections.push(format!("Ignoring trusted key {}: {}", path.display(), e))
+            }
+        }
+        KeySet { keys }
+    }
+
+    /// Parses a public key file
+    fn parse(data: &[u8]) -> Result<VerifyingKey, String> {
+        // Decode the key
+        let key = match data {
+            _ if data.trim_ascii_start().starts_with(b"-----BEGIN") => {
+                let pem = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+                return VerifyingKey::from_public_key_pem(pem).map_err(|e| e.to_string());
+            },
+            _ if data.len() == 32 => data.to_vec(),
+            _ => decode_hex(data).ok_or("key is neither raw, hex nor PEM encoded")?
+        };
+
+        // Create the key
+        let key: [u8; 32] = key.try_into().map_err(|_| "invalid key length")?;
+        VerifyingKey::from_bytes(&key).map_err(|e| e.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{ Signer, SigningKey };
+
+    /// The signing key that belongs to [`PUBLIC_KEY_HEX`] and [`PUBLIC_KEY_PEM`]
+    const SECRET_KEY: [u8; 32] = [7; 32];
+    /// The hex encoded public key
+    const PUBLIC_KEY_HEX: &str = "ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c";
+    /// The PEM encoded public key
+    const PUBLIC_KEY_PEM: &str = concat!(
+        "-----BEGIN PUBLIC KEY-----\n",
+        "MCowBQYDK2VwAyEA6kpsY+KcUgq+9VB7Ey7F+ZVHdq6+vnuSQh7qaRRG0iw=\n",
+        "-----END PUBLIC KEY-----\n"
+    );
+
+    /// Signs `data` with `key` and returns the raw signature
+    fn sign(key: [u8; 32], data: &[u8]) -> Vec<u8> {
+        SigningKey::from_bytes(&key).sign(data).to_bytes().to_vec()
+    }
+
+    /// Writes the key files and loads them
+    fn load(name: &str, files: &[(&str, &[u8])]) -> (KeySet, Vec<String>) {
+        let directory = fsext::test_dir(name);
+        for (file, data) in files {
+            fs::write(directory.join(file), data).expect("failed to write key file");
+        }
+        let mut rejections = Vec::new();
+        (TrustedKeys::new(directory).load(&mut rejections), rejections)
+    }
+
+    #[test]
+    fn decode_hex_strings() {
+        assert_eq!(decode_hex(b" 00ff10Ab\n"), Some(vec![0x00, 0xff, 0x10, 0xab]));
+        assert_eq!(decode_hex(b""), Some(Vec::new()));
+        assert_eq!(decode_hex(b"abc"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+    }
+
+    #[test]
+    fn parse_key_encodings() {
+        let raw = decode_hex(PUBLIC_KEY_HEX.as_bytes()).expect("invalid hex key");
+        let key = SigningKey::from_bytes(&SECRET_KEY).verifying_key();
+        assert_eq!(TrustedKeys::parse(&raw), Ok(key));
+        assert_eq!(TrustedKeys::parse(format!("{}\n", PUBLIC_KEY_HEX).as_bytes()), Ok(key));
+        assert_eq!(TrustedKeys::parse(PUBLIC_KEY_PEM.as_bytes()), Ok(key));
+        assert!(TrustedKeys::parse(b"not a key").is_err());
+        assert!(TrustedKeys::parse(b"00ff").is_err());
+    }
+
+    #[test]
+    fn verify_signatures() {
+        let (keys, rejections) = load("signature-verify", &[("key.pem", PUBLIC_KEY_PEM.as_bytes()), ("invalid", b"x")]);
+        assert_eq!(rejections.len(), 1);
+        assert!(rejections[0].contains("Ignoring trusted key"));
+
+        // Raw and hex encoded signatures are accepted
+        let signature = sign(SECRET_KEY, b"backend a\n");
+        assert_eq!(keys.verify(b"backend a\n", &signature), Ok(()));
+        let hex: String = signature.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(keys.verify(b"backend a\n", format!("{}\n", hex).as_bytes()), Ok(()));
+
+        // Modified data, foreign keys and malformed signatures are rejected
+        assert!(keys.verify(b"backend b\n", &signature).is_err());
+        assert!(keys.verify(b"backend a\n", &sign([8; 32], b"backend a\n")).is_err());
+        assert_eq!(keys.verify(b"backend a\n", b"xyz"), Err("signature is neither raw nor hex encoded".to_string()));
+        assert_eq!(keys.verify(b"backend a\n", b"00ff"), Err("invalid signature length".to_string()));
+    }
+
+    #[test]
+    fn missing_key_directories() {
+        let mut rejections = Vec::new();
+        let keys = TrustedKeys::new(fsext::test_dir("signature-missing").join("missing")).load(&mut rejections);
+        assert_eq!(rejections.len(), 1);
+        assert!(keys.verify(b"data", &sign(SECRET_KEY, b"data")).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{ fsext, log };
+use sha2::{ Sha512, Digest };
+use std::{
+    collections::HashMap,
+    fs::{ File, Metadata },
+    io::Result,
+    path::{ Path, PathBuf },
+    sync::{ Arc, Mutex, PoisonError },
+    time::{ Duration, Instant, SystemTime }
+};
+
+
+/// The interval at which files that are locked by a writer are re-checked, since releasing a lock emits no inotify event
+const LOCK_RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+
+/// A snapshot of a file's state
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Signature {
+    /// The file size
+    size: u64,
+    /// The modification time
+    mtime: Option<SystemTime>,
+    /// The hash of the file contents
+    hash: Vec<u8>
+}
+impl Signature {
+    /// Creates a new signature from the file metadata and contents
+    pub fn new(metadata: &Metadata, data: &[u8]) -> Self {
+        let hash = Sha512::digest(data).to_vec();
+        Self { size: metadata.len(), mtime: metadata.modified().ok(), hash }
+    }
+}
+
+
+/// The observed state of a file
+#[derive(Debug)]
+struct Observation {
+    /// The last observed signature
+    signature: Signature,
+    /// The point in time since which the file has had the current signature
+    since: Instant,
+    /// Whether the file was locked by a writer during the last observation
+    locked: bool,
+    /// Whether the current signature has been considered as stable
+    settled: bool,
+    /// Whether a deferral of the current signature has been logged
+    logged: bool,
+    /// The last stable contents
+    stable: Option<Vec<u8>>
+}
+
+
+/// Tracks whether files are still being written
+///
+/// A file is considered stable if it has been released by a writer that held an exclusive advisory lock (`flock`), or if
+/// its size, modification time and content hash have stayed the same across the settle window. The settle window starts
+/// at the file's modification time, so files that have not been touched for a while are stable immediately.
+#[derive(Debug, Clone)]
+pub struct StabilityTracker {
+    /// The settle window
+    window: Duration,
+    /// The observed files (shared between all clones)
+    observations: Arc<Mutex<HashMap<PathBuf, Observation>>>
+}
+impl StabilityTracker {
+    /// Creates a new stability tracker
+    pub fn new(window: Duration) -> Self {
+        Self { window, observations: Default::default() }
+    }
+
+    /// Reads up to `limit` bytes from `file` located at `path` and returns the contents if the file is stable, or the last
+    /// stable contents (if any) if the file is still being written
+    pub fn read<F>(&self, path: F, file: File, limit: u64) -> Result<Option<Vec<u8>>> where F: AsRef<Path> {
+        // Read the file while holding a shared lock
+        let path = path.as_ref();
+        let locked = !fsext::try_lock_shared(&file)?;
+        let metadata = file.metadata()?;
+        let data = fsext::read_limited(file, limit)?;
+        let signature = Signature::new(&metadata, &data);
+
+        // Get or reset the observation
+        let now = Instant::now();
+        let since = Self::since(&metadata, now);
+        let mut observations = self.observations.lock().unwrap_or_else(PoisonError::into_inner);
+        let observation = observations.entry(path.to_path_buf()).or_insert_with(|| Observation {
+            signature: signature.clone(), since, locked: false, settled: false, logged: false, stable: None
+        });
+        if observation.signature != signature {
+            observation.signature = signature;
+            observation.since = since;
+            observation.settled = false;
+            observation.logged = false;
+        }
+
+        // Evaluate the stability
+        match observation.locked {
+            _ if locked => observation.settled = false,
+            true => observation.settled = true,
+            false => observation.settled |= now.duration_since(observation.since) >= self.window
+        }
+        observation.locked = locked;
+
+        // Return the stable data
+        if observation.settled {
+            observation.stable = Some(data.clone());
+            return Ok(Some(data));
+        }
+        if !observation.logged {
+            log::info("fragment_deferred", format!("Deferring {}: fragment is still being written", path.display()))
+                .field("fragment", path.display().to_string()).emit();
+            observation.logged = true;
+        }
+        Ok(observation.stable.clone())
+    }
+
+    /// Whether the last observation of the file at `path` has been considered as stable
+    pub fn is_settled<F>(&self, path: F) -> bool where F: AsRef<Path> {
+        let observations = self.observations.lock().unwrap_or_else(PoisonError::into_inner);
+        observations.get(path.as_ref()).is_some_and(|observation| observation.settled)
+    }
+    /// Whether some of the observed files are still being written
+    pub fn is_settling(&self) -> bool {
+        let observations = self.observations.lock().unwrap_or_else(PoisonError::into_inner);
+        observations.values().any(|observation| !observation.settled)
+    }
+    /// The point in time at which the next file settles or a file that is locked by a writer should be re-checked (if any)
+    pub fn settle_deadline(&self) -> Option<Instant> {
+        let observations = self.observations.lock().unwrap_or_else(PoisonError::into_inner);
+        let recheck = Instant::now() + LOCK_RECHECK_INTERVAL;
+        observations.values().filter(|observation| !observation.settled)
+            .map(|observation| match observation.locked {
+                true => recheck,
+                false => observation.since + self.window
+            })
+            .min()
+    }
+    /// Forgets all observed files that are not in `paths`
+    pub fn retain(&self, paths: &[PathBuf]) {
+        let mut observations = self.observations.lock().unwrap_or_else(PoisonError::into_inner);
+        observations.retain(|path, _| paths.contains(path));
+    }
+
+    /// Computes the point in time since when the file has been unmodified
+    fn since(metadata: &Metadata, now: Instant) -> Instant {
+        metadata.modified().ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .and_then(|age| now.checked_sub(age))
+            .unwrap_or(now)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ fs::{ self, FileTimes }, os::unix::io::AsRawFd };
+
+    /// Writes `data` to `path` and backdates its modification time by `age`
+    fn write(path: &Path, data: &str, age: Duration) {
+        fs::write(path, data).expect("failed to write file");
+        let file = File::options().write(true).open(path).expect("failed to open file");
+        file.set_times(FileTimes::new().set_modified(SystemTime::now() - age)).expect("failed to set modification time");
+    }
+
+    /// Reads `path` via `tracker`
+    fn read(tracker: &StabilityTracker, path: &Path) -> Option<Vec<u8>> {
+        let file = File::open(path).expect("failed to open file");
+        tracker.read(path, file, 1024).expect("failed to read file")
+    }
+
+    #[test]
+    fn untouched_files_are_stable() {
+        let path = fsext::test_dir("stability-untouched").join("a.cfg");
+        let tracker = StabilityTracker::new(Duration::from_secs(60));
+        write(&path, "old", Duration::from_secs(120));
+        assert_eq!(read(&tracker, &path), Some(b"old".to_vec()));
+        assert!(tracker.is_settled(&path));
+        assert!(!tracker.is_settling());
+        assert_eq!(tracker.settle_deadline(), None);
+    }
+
+    #[test]
+    fn changed_files_keep_the_last_stable_version() {
+        let path = fsext::test_dir("stability-changed").join("a.cfg");
+        let tracker = StabilityTracker::new(Duration::from_secs(60));
+
+        // A fresh file has no stable version yet
+        write(&path, "new", Duration::ZERO);
+        assert_eq!(read(&tracker, &path), None);
+        assert!(!tracker.is_settled(&path));
+        assert!(tracker.is_settling());
+        assert!(tracker.settle_deadline().is_some_and(|deadline| deadline > Instant::now() + Duration::from_secs(30)));
+
+        // Once it has settled, the stable version is kept while the file is rewritten
+        write(&path, "new", Duration::from_secs(120));
+        assert_eq!(read(&tracker, &path), Some(b"new".to_vec()));
+        write(&path, "newer", Duration::ZERO);
+        assert_eq!(read(&tracker, &path), Some(b"new".to_vec()));
+        assert!(!tracker.is_settled(&path));
+    }
+
+    #[test]
+    fn locked_files_settle_once_released() {
+        let path = fsext::test_dir("stability-locked").join("a.cfg");
+        let tracker = StabilityTracker::new(Duration::from_secs(60));
+        write(&path, "locked", Duration::from_secs(120));
+
+        // Hold an exclusive lock like a writer
+        let writer = File::open(&path).expect("failed to open file");
+        // SAFETY: `flock` only operates on the valid file descriptor owned by `writer`
+        assert_eq!(unsafe { libc::flock(writer.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }, 0);
+        assert_eq!(read(&tracker, &path), None);
+        assert!(tracker.settle_deadline().is_some_and(|deadline| deadline <= Instant::now() + LOCK_RECHECK_INTERVAL));
+
+        // Releasing the lock settles the file immediately, even if it has just been modified
+        drop(writer);
+        write(&path, "unlocked", Duration::ZERO);
+        assert_eq!(read(&tracker, &path), Some(b"unlocked".to_vec()));
+        assert!(tracker.is_settled(&path));
+    }
+
+    #[test]
+    fn retain_forgets_removed_files() {
+        let directory = fsext::test_dir("stability-retain");
+        let (a, b) = (directory.join("a.cfg"), directory.join("b.cfg"));
+        let tracker = StabilityTracker::new(Duration::from_secs(60));
+        write(&a, "a", Duration::ZERO);
+        write(&b, "b", Duration::ZERO);
+        assert_eq!((read(&tracker, &a), read(&tracker, &b)), (None, None));
+        tracker.retain(std::slice::from_ref(&a));
+        assert!(tracker.is_settling());
+        tracker.retain(&[]);
+        assert!(!tracker.is_settling());
+    }
+}
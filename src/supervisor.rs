@@ -0,0 +1,190 @@
+use crate::{ backoff::Backoff, events::child::ChildExit };
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    time::{ Duration, Instant }
+};
+
+
+/// When HAProxy is restarted after it has exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// HAProxy is always restarted
+    Always,
+    /// HAProxy is only restarted if it has failed (i.e. exited with a non-zero code or has been killed by a signal)
+    OnFailure,
+    /// HAProxy is never restarted and the daemon exits
+    Never
+}
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "on-failure" => Ok(Self::OnFailure),
+            "never" => Ok(Self::Never),
+            s => Err(format!("Invalid restart policy: {} (expected always, on-failure or never)", s))
+        }
+    }
+}
+
+
+/// The escalation if HAProxy is crash-looping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashAction {
+    /// The daemon exits
+    Exit,
+    /// The last-known-good config is restored (if any); the daemon exits if there is none or if it crashes as well
+    Fallback
+}
+impl FromStr for CrashAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exit" => Ok(Self::Exit),
+            "fallback" => Ok(Self::Fallback),
+            s => Err(format!("Invalid crash action: {} (expected exit or fallback)", s))
+        }
+    }
+}
+
+
+/// How to proceed after HAProxy has exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Restart HAProxy after the given delay
+    Restart(Duration),
+    /// Restore the last-known-good config and restart HAProxy immediately
+    Fallback,
+    /// Exit the daemon
+    Exit
+}
+
+
+/// Decides whether and when HAProxy is restarted after it has exited
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    /// The restart policy
+    policy: RestartPolicy,
+    /// The maximum amount of restarts within the window before HAProxy is considered to be crash-looping
+    max_restarts: usize,
+    /// The crash window; HAProxy is also considered to be stable once it has been running for this time
+    window: Duration,
+    /// The escalation if HAProxy is crash-looping
+    action: CrashAction,
+    /// The backoff between restarts
+    backoff: Backoff,
+    /// The points in time of the recent crashes within the window
+    crashes: VecDeque<Instant>
+}
+impl Supervisor {
+    /// The initial delay between restarts
+    const INITIAL_DELAY: Duration = Duration::from_secs(1);
+    /// The maximum delay between restarts
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+
+    /// Creates a new supervisor
+    pub fn new(policy: RestartPolicy, max_restarts: usize, window: Duration, action: CrashAction) -> Self {
+        let backoff = Backoff::new(Self::INITIAL_DELAY, Self::MAX_DELAY);
+        Self { policy, max_restarts, window, action, backoff, crashes: VecDeque::new() }
+    }
+
+    /// Whether a process that has been running for `uptime` is considered to be stable
+    pub fn is_stable(&self, uptime: Duration) -> bool {
+        uptime >= self.window
+    }
+
+    /// Records the exit of HAProxy and decides how to proceed; `can_fallback` indicates whether a last-known-good config
+    /// that differs from the current config is available
+    pub fn decide(&mut self, exit: &ChildExit, can_fallback: bool) -> Decision {
+        // Forget the previous crashes if HAProxy has been stable
+        if self.is_stable(exit.uptime) {
+            self.backoff.reset();
+            self.crashes.clear();
+        }
+
+        // Apply the restart policy
+        let failed = !exit.status.success();
+        match self.policy {
+            RestartPolicy::Never => return Decision::Exit,
+            RestartPolicy::OnFailure if !failed => return Decision::Exit,
+            _ => ()
+        }
+
+        // Record the crash and detect crash-loops
+        let now = Instant::now();
+        self.crashes.retain(|crash| now.duration_since(*crash) < self.window);
+        self.crashes.push_back(now);
+        if self.crashes.len() > self.max_restarts {
+            self.crashes.clear();
+            return match self.action {
+                CrashAction::Fallback if can_fallback => Decision::Fallback,
+                _ => Decision::Exit
+            };
+        }
+        Decision::Restart(self.backoff.next())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ os::unix::process::ExitStatusExt, process::ExitStatus };
+
+    /// Creates an exit with the exit `code` after `uptime` seconds
+    fn exit(code: i32, uptime: u64) -> ChildExit {
+        let (status, uptime) = (ExitStatus::from_raw(code << 8), Duration::from_secs(uptime));
+        ChildExit { pid: 42, status, uptime, generation: 1 }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("always".parse(), Ok(RestartPolicy::Always));
+        assert_eq!("on-failure".parse(), Ok(RestartPolicy::OnFailure));
+        assert_eq!("never".parse(), Ok(RestartPolicy::Never));
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+        assert_eq!("exit".parse(), Ok(CrashAction::Exit));
+        assert_eq!("fallback".parse(), Ok(CrashAction::Fallback));
+        assert!("restart".parse::<CrashAction>().is_err());
+    }
+
+    #[test]
+    fn restart_policies() {
+        let window = Duration::from_secs(60);
+        let mut always = Supervisor::new(RestartPolicy::Always, 5, window, CrashAction::Exit);
+        assert_eq!(always.decide(&exit(0, 1), false), Decision::Restart(Duration::from_secs(1)));
+
+        let mut on_failure = Supervisor::new(RestartPolicy::OnFailure, 5, window, CrashAction::Exit);
+        assert_eq!(on_failure.decide(&exit(0, 1), false), Decision::Exit);
+        assert_eq!(on_failure.decide(&exit(1, 1), false), Decision::Restart(Duration::from_secs(1)));
+
+        let mut never = Supervisor::new(RestartPolicy::Never, 5, window, CrashAction::Exit);
+        assert_eq!(never.decide(&exit(1, 1), false), Decision::Exit);
+    }
+
+    #[test]
+    fn crash_loops_escalate() {
+        let mut supervisor = Supervisor::new(RestartPolicy::Always, 2, Duration::from_secs(60), CrashAction::Fallback);
+        assert_eq!(supervisor.decide(&exit(1, 1), true), Decision::Restart(Duration::from_secs(1)));
+        assert_eq!(supervisor.decide(&exit(1, 1), true), Decision::Restart(Duration::from_secs(2)));
+        assert_eq!(supervisor.decide(&exit(1, 1), true), Decision::Fallback);
+
+        // Without a fallback config, the daemon exits
+        assert_eq!(supervisor.decide(&exit(1, 1), false), Decision::Restart(Duration::from_secs(4)));
+        assert_eq!(supervisor.decide(&exit(1, 1), false), Decision::Restart(Duration::from_secs(8)));
+        assert_eq!(supervisor.decide(&exit(1, 1), false), Decision::Exit);
+    }
+
+    #[test]
+    fn stable_runs_reset_the_history() {
+        let mut supervisor = Supervisor::new(RestartPolicy::Always, 1, Duration::from_secs(60), CrashAction::Exit);
+        assert!(!supervisor.is_stable(Duration::from_secs(59)));
+        assert!(supervisor.is_stable(Duration::from_secs(60)));
+        assert_eq!(supervisor.decide(&exit(1, 1), false), Decision::Restart(Duration::from_secs(1)));
+        assert_eq!(supervisor.decide(&exit(1, 60), false), Decision::Restart(Duration::from_secs(1)));
+        assert_eq!(supervisor.decide(&exit(1, 1), false), Decision::Exit);
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    ffi::{ CString, OsStr },
+    fs::{ File, OpenOptions },
+    io::{ Error, ErrorKind, Result },
+    mem,
+    os::unix::{ ffi::OsStrExt, fs::OpenOptionsExt, io::{ AsRawFd, FromRawFd } },
+    path::Path,
+    str::FromStr
+};
+
+
+/// The policy for symlinks within the inbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are followed everywhere
+    Follow,
+    /// Symlinks are only followed if they resolve to a file within the inbox
+    Inbox,
+    /// Symlinks are refused
+    Refuse
+}
+impl SymlinkPolicy {
+    /// Opens the file `name` within `directory` for reading according to the policy
+    ///
+    /// # Note
+    /// The file is opened non-blocking to avoid hanging on FIFOs; an error is returned if the opened file is not a regular
+    /// file.
+    pub fn open<D, N>(&self, directory: D, name: N) -> Result<File> where D: AsRef<Path>, N: AsRef<OsStr> {
+        let (directory, name) = (directory.as_ref(), name.as_ref());
+        let file = match self {
+            Self::Follow => Self::open_flags(directory.join(name), 0)?,
+            Self::Inbox => Self::open_beneath(directory, name)?,
+            Self::Refuse => Self::open_flags(directory.join(name), libc::O_NOFOLLOW).map_err(|e| match e {
+                e if e.raw_os_error() == Some(libc::ELOOP) => Error::other("symlinks are not allowed"),
+                e => e
+            })?
+        };
+
+        // Ensure that we have opened a regular file
+        match file.metadata()?.is_file() {
+            true => Ok(file),
+            false => Err(Error::other("not a regular file"))
+        }
+    }
+
+    /// Opens `path` non-blocking with some additional `flags`
+    fn open_flags<F>(path: F, flags: i32) -> Result<File> where F: AsRef<Path> {
+        OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK | flags).open(path)
+    }
+
+    /// Opens `name` and ensures that all symlinks resolve to files within `directory`
+    fn open_beneath(directory: &Path, name: &OsStr) -> Result<File> {
+        let escaped = || Error::other("symlink points outside of the inbox");
+
+        // Open the file relative to the directory via `openat2`
+        let directory_fd = File::open(directory)?;
+        let name_c = CString::new(name.as_bytes()).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        // SAFETY: `open_how` is a plain C struct that is valid if zeroed
+        let mut how: libc::open_how = unsafe { mem::zeroed() };
+        how.flags = (libc::O_RDONLY | libc::O_NONBLOCK | libc::O_CLOEXEC) as u64;
+        how.resolve = libc::RESOLVE_BENEATH;
+        // SAFETY: All pointers are valid for the duration of the call and the size matches the struct
+        let fd = unsafe {
+            libc::syscall(libc::SYS_openat2, directory_fd.as_raw_fd(), name_c.as_ptr(), &how as *const libc::open_how,
+                mem::size_of::<libc::open_how>())
+        };
+        match fd {
+            // SAFETY: The file descriptor has just been opened by us and is not owned by anything else
+            fd if fd >= 0 => return Ok(unsafe { File::from_raw_fd(fd as i32) }),
+            _ => match Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::EXDEV) => return Err(escaped()),
+                // Seccomp profiles that do not know `openat2` may deny it with `EPERM` instead of `ENOSYS`
+                e if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) => (),
+                e => return Err(e)
+            }
+        }
+
+        // Fallback for kernels or sandboxes without `openat2`: canonicalize, check and open the resolved path without following it
+        let directory = directory.canonicalize()?;
+        let path = directory.join(name).canonicalize()?;
+        if !path.starts_with(&directory) {
+            return Err(escaped());
+        }
+        Self::open_flags(path, libc::O_NOFOLLOW)
+    }
+}
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "inbox" => Ok(Self::Inbox),
+            "refuse" => Ok(Self::Refuse),
+            s => Err(format!("Invalid symlink policy: {} (expected follow, inbox or refuse)", s))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext;
+    use std::{ fs, io::Read, os::unix::fs::symlink };
+
+    /// Opens `name` within `directory` via `policy` and returns its contents or the error message
+    fn read(policy: SymlinkPolicy, directory: &Path, name: &str) -> std::result::Result<String, String> {
+        let mut data = String::new();
+        let mut file = policy.open(directory, name).map_err(|e| e.to_string())?;
+        file.read_to_string(&mut data).map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("follow".parse(), Ok(SymlinkPolicy::Follow));
+        assert_eq!("inbox".parse(), Ok(SymlinkPolicy::Inbox));
+        assert_eq!("refuse".parse(), Ok(SymlinkPolicy::Refuse));
+        assert!("contain".parse::<SymlinkPolicy>().is_err());
+    }
+
+    #[test]
+    fn open_according_to_policy() {
+        let (inbox, outside) = (fsext::test_dir("symlink-inbox"), fsext::test_dir("symlink-outside"));
+        fs::write(inbox.join("file.cfg"), "inside").expect("failed to write file");
+        fs::write(outside.join("file.cfg"), "outside").expect("failed to write file");
+        symlink("file.cfg", inbox.join("internal.cfg")).expect("failed to create symlink");
+        symlink(outside.join("file.cfg"), inbox.join("external.cfg")).expect("failed to create symlink");
+        symlink("../symlink-outside/file.cfg", inbox.join("relative.cfg")).expect("failed to create symlink");
+
+        // Regular files are always opened
+        for policy in [SymlinkPolicy::Follow, SymlinkPolicy::Inbox, SymlinkPolicy::Refuse] {
+            assert_eq!(read(policy, &inbox, "file.cfg"), Ok("inside".to_string()));
+        }
+
+        // Symlinks are followed depending on their target
+        assert_eq!(read(SymlinkPolicy::Follow, &inbox, "internal.cfg"), Ok("inside".to_string()));
+        assert_eq!(read(SymlinkPolicy::Follow, &inbox, "external.cfg"), Ok("outside".to_string()));
+        assert_eq!(read(SymlinkPolicy::Inbox, &inbox, "internal.cfg"), Ok("inside".to_string()));
+        let escaped = Err("symlink points outside of the inbox".to_string());
+        assert_eq!(read(SymlinkPolicy::Inbox, &inbox, "external.cfg"), escaped);
+        assert_eq!(read(SymlinkPolicy::Inbox, &inbox, "relative.cfg"), escaped);
+        let refused = Err("symlinks are not allowed".to_string());
+        assert_eq!(read(SymlinkPolicy::Refuse, &inbox, "internal.cfg"), refused);
+        assert_eq!(read(SymlinkPolicy::Refuse, &inbox, "external.cfg"), refused);
+    }
+
+    #[test]
+    fn special_files_are_rejected() {
+        let inbox = fsext::test_dir("symlink-special");
+        fs::create_dir(inbox.join("directory.cfg")).expect("failed to create directory");
+        let fifo = CString::new(inbox.join("fifo.cfg").as_os_str().as_bytes()).expect("invalid path");
+        // SAFETY: `fifo` is a valid C string
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        // FIFOs must not block
+        for policy in [SymlinkPolicy::Follow, SymlinkPolicy::Inbox, SymlinkPolicy::Refuse] {
+            assert_eq!(read(policy, &inbox, "directory.cfg"), Err("not a regular file".to_string()));
+            assert_eq!(read(policy, &inbox, "fifo.cfg"), Err("not a regular file".to_string()));
+        }
+    }
+}
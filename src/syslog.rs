@@ -0,0 +1,312 @@
+use crate::log::{ self, Value };
+use std::{
+    fmt::{ self, Display, Formatter },
+    str::FromStr
+};
+
+
+/// The names of the syslog facilities (indexed by facility number)
+const FACILITIES: &[&str] = &[
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp", "audit",
+    "alert", "clock", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7"
+];
+/// The names of the syslog severities (indexed by severity number)
+const SEVERITIES: &[&str] = &["emerg", "alert", "crit", "err", "warning", "notice", "info", "debug"];
+
+
+/// The octal permission bits of the syslog socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketMode(pub u32);
+impl FromStr for SocketMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(Self(mode)),
+            _ => Err(format!("Invalid socket mode: {} (expected octal permission bits like 0660)", s))
+        }
+    }
+}
+
+
+/// The numeric owner of the syslog socket (`UID`, `UID:GID` or `:GID`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketOwner {
+    /// The user ID (if it should be changed)
+    pub uid: Option<u32>,
+    /// The group ID (if it should be changed)
+    pub gid: Option<u32>
+}
+impl FromStr for SocketOwner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid socket owner: {} (expected UID, UID:GID or :GID)", s);
+        let (uid, gid) = s.split_once(':').unwrap_or((s, ""));
+        let parse = |id: &str| match id.is_empty() {
+            true => Ok(None),
+            false => id.parse().map(Some).map_err(|_| invalid())
+        };
+        match (parse(uid)?, parse(gid)?) {
+            (None, None) => Err(invalid()),
+            (uid, gid) => Ok(Self { uid, gid })
+        }
+    }
+}
+
+
+/// A syslog message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    /// The facility number (if the message has a priority)
+    pub facility: Option<u8>,
+    /// The severity number (if the message has a priority)
+    pub severity: Option<u8>,
+    /// The timestamp as sent by the sender (if any)
+    pub timestamp: Option<String>,
+    /// The hostname (if any)
+    pub hostname: Option<String>,
+    /// The application name or tag (if any)
+    pub app: Option<String>,
+    /// The process ID (if any)
+    pub pid: Option<String>,
+    /// The message ID (RFC 5424 only)
+    pub msgid: Option<String>,
+    /// The structured data (RFC 5424 only)
+    pub structured_data: Option<String>,
+    /// The message text
+    pub text: String
+}
+impl Message {
+    /// Parses an RFC 5424 or RFC 3164 message; unparseable parts are kept as message text
+    pub fn parse(datagram: &[u8]) -> Self {
+        let datagram = String::from_utf8_lossy(datagram);
+        let datagram = datagram.trim_end_matches(['\n', '\r', '\0']);
+
+        // Parse the priority
+        let mut this = Self::default();
+        let Some((priority, rest)) = Self::priority(datagram) else {
+            this.text = datagram.to_string();
+            return this;
+        };
+        (this.facility, this.severity) = (Some(priority / 8), Some(priority % 8));
+
+        // Parse the header depending on the version
+        match rest.strip_prefix("1 ") {
+            Some(rest) => this.parse_5424(rest),
+            None => this.parse_3164(rest)
+        }
+        this
+    }
+
+    /// The facility name (if any)
+    pub fn facility_name(&self) -> Option<&'static str> {
+        FACILITIES.get(usize::from(self.facility?)).copied()
+    }
+    /// The severity name (if any)
+    pub fn severity_name(&self) -> Option<&'static str> {
+        SEVERITIES.get(usize::from(self.severity?)).copied()
+    }
+
+    /// Formats the message as JSON object
+    pub fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| value.clone().map(Value::String);
+        let fields = [
+            ("received", Some(Value::String(log::now()))), ("facility", self.facility_name().map(Value::from)),
+            ("severity", self.severity_name().map(Value::from)), ("timestamp", optional(&self.timestamp)),
+            ("hostname", optional(&self.hostname)), ("app", optional(&self.app)), ("pid", optional(&self.pid)),
+            ("msgid", optional(&self.msgid)), ("structured_data", optional(&self.structured_data)),
+            ("message", Some(Value::String(self.text.clone())))
+        ];
+        log::json(fields.iter().filter_map(|(name, value)| Some((*name, value.as_ref()?))))
+    }
+
+    /// Splits the priority (`<PRI>`) from `datagram`
+    fn priority(datagram: &str) -> Option<(u8, &str)> {
+        let (priority, rest) = datagram.strip_prefix('<')?.split_once('>')?;
+        let valid = (1..=3).contains(&priority.len()) && priority.bytes().all(|byte| byte.is_ascii_digit());
+        match priority.parse::<u8>() {
+            Ok(priority) if valid && priority < 192 => Some((priority, rest)),
+            _ => None
+        }
+    }
+
+    /// Parses the RFC 5424 header after the version (`TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD [MSG]`)
+    fn parse_5424(&mut self, rest: &str) {
+        // Parse the space-separated header fields where `-` denotes an absent value
+        let mut rest = rest;
+        let mut fields = [None, None, None, None, None];
+        for field in &mut fields {
+            let (value, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            *field = (value != "-" && !value.is_empty()).then(|| value.to_string());
+            rest = tail;
+        }
+        [self.timestamp, self.hostname, self.app, self.pid, self.msgid] = fields;
+
+        // Parse the structured data elements
+        let (structured_data, text) = Self::structured_data(rest);
+        self.structured_data = structured_data.map(str::to_string);
+        self.text = text.strip_prefix('\u{feff}').unwrap_or(text).to_string();
+    }
+    /// Splits the structured data (`-` or one or more `[...]` elements) from the message text
+    fn structured_data(rest: &str) -> (Option<&str>, &str) {
+        if let Some(text) = rest.strip_prefix('-') {
+            return (None, text.strip_prefix(' ').unwrap_or(text));
+        }
+
+        // Find the end of the last element, honoring escaped characters within parameter values
+        let (mut end, mut depth, mut quoted, mut escaped) = (0, 0, false, false);
+        for (index, char) in rest.char_indices() {
+            match char {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' if depth > 0 => quoted = !quoted,
+                '[' if !quoted && depth == 0 => depth = 1,
+                ']' if !quoted && depth == 1 => {
+                    depth = 0;
+                    end = index + 1;
+                },
+                _ if depth == 0 => break,
+                _ => ()
+            }
+        }
+        match end {
+            0 => (None, rest),
+            end => (Some(&rest[..end]), rest[end..].strip_prefix(' ').unwrap_or(&rest[end..]))
+        }
+    }
+
+    /// Parses the RFC 3164 header (`Mmm dd hh:mm:ss [HOSTNAME] TAG[PID]: MSG`); the hostname is optional since local
+    /// senders like HAProxy omit it
+    fn parse_3164(&mut self, rest: &str) {
+        // Parse the timestamp
+        let mut rest = rest;
+        if let Some(timestamp) = rest.get(..15).filter(|timestamp| Self::is_3164_timestamp(timestamp)) {
+            self.timestamp = Some(timestamp.to_string());
+            rest = rest[15..].strip_prefix(' ').unwrap_or(&rest[15..]);
+        }
+
+        // Parse the hostname if the first word is not the tag
+        let first = rest.split(' ').next().unwrap_or_default();
+        if self.timestamp.is_some() && !first.ends_with(':') && !first.contains('[') && rest.contains(' ') {
+            self.hostname = Some(first.to_string());
+            rest = &rest[first.len() + 1..];
+        }
+
+        // Parse the tag and the PID
+        let tag_len = rest.find(|char: char| !(char.is_alphanumeric() || "-_./[]".contains(char))).unwrap_or(rest.len());
+        let (tag, text) = rest.split_at(tag_len);
+        let Some(text) = text.strip_prefix(':') else {
+            self.text = rest.to_string();
+            return;
+        };
+        let (app, pid) = match tag.strip_suffix(']').and_then(|tag| tag.split_once('[')) {
+            Some((app, pid)) => (app, Some(pid.to_string())),
+            None => (tag, None)
+        };
+        (self.app, self.pid) = ((!app.is_empty()).then(|| app.to_string()), pid);
+        self.text = text.strip_prefix(' ').unwrap_or(text).to_string();
+    }
+    /// Whether `timestamp` has the RFC 3164 format (`Mmm dd hh:mm:ss`)
+    fn is_3164_timestamp(timestamp: &str) -> bool {
+        let bytes = timestamp.as_bytes();
+        let digit = |index: usize| bytes[index].is_ascii_digit();
+        bytes.len() == 15 && bytes[..3].iter().all(u8::is_ascii_alphabetic) && bytes[3] == b' '
+            && (bytes[4] == b' ' || digit(4)) && digit(5) && bytes[6] == b' ' && digit(7) && digit(8) && bytes[9] == b':'
+            && digit(10) && digit(11) && bytes[12] == b':' && digit(13) && digit(14)
+    }
+}
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (&self.app, &self.pid) {
+            (Some(app), Some(pid)) => write!(f, "{}[{}]: {}", app, pid, self.text),
+            (Some(app), None) => write!(f, "{}: {}", app, self.text),
+            _ => write!(f, "{}", self.text)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an owned `Some` string
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn rfc3164_without_hostname() {
+        let message = Message::parse(b"<134>Oct 18 12:34:56 haproxy[1234]: 127.0.0.1:5000 [18/Oct/2026] fe be/srv\n");
+        assert_eq!(message, Message {
+            facility: Some(16), severity: Some(6), timestamp: some("Oct 18 12:34:56"), hostname: None,
+            app: some("haproxy"), pid: some("1234"), text: "127.0.0.1:5000 [18/Oct/2026] fe be/srv".to_string(),
+            ..Message::default()
+        });
+        assert_eq!(message.facility_name(), Some("local0"));
+        assert_eq!(message.severity_name(), Some("info"));
+    }
+
+    #[test]
+    fn rfc3164_with_hostname() {
+        let message = Message::parse(b"<13>Oct  8 01:02:03 lb-1 haproxy[7]: Proxy fe started.");
+        assert_eq!(message.timestamp, some("Oct  8 01:02:03"));
+        assert_eq!(message.hostname, some("lb-1"));
+        assert_eq!((message.app, message.pid), (some("haproxy"), some("7")));
+        assert_eq!(message.text, "Proxy fe started.");
+
+        // A tag without a PID
+        let message = Message::parse(b"<13>Oct 18 12:34:56 lb-1 haproxy: text");
+        assert_eq!((message.hostname, message.app, message.pid), (some("lb-1"), some("haproxy"), None));
+    }
+
+    #[test]
+    fn rfc3164_without_header() {
+        let message = Message::parse(b"<13>free text without a tag");
+        assert_eq!((message.timestamp, message.app), (None, None));
+        assert_eq!(message.text, "free text without a tag");
+
+        // Invalid priorities are kept as text
+        for datagram in ["<192>text", "<abc>text", "<>text", "no priority"] {
+            let message = Message::parse(datagram.as_bytes());
+            assert_eq!((message.facility, message.text.as_str()), (None, datagram));
+        }
+    }
+
+    #[test]
+    fn rfc5424_structured_data() {
+        let datagram = br#"<165>1 2026-10-18T12:34:56.000Z lb-1 haproxy 1234 ID47 [a@1 x="1"][b@1 y="q\"]"] Hello"#;
+        let message = Message::parse(datagram);
+        assert_eq!(message, Message {
+            facility: Some(20), severity: Some(5), timestamp: some("2026-10-18T12:34:56.000Z"), hostname: some("lb-1"),
+            app: some("haproxy"), pid: some("1234"), msgid: some("ID47"),
+            structured_data: some(r#"[a@1 x="1"][b@1 y="q\"]"]"#), text: "Hello".to_string()
+        });
+    }
+
+    #[test]
+    fn rfc5424_without_structured_data() {
+        let message = Message::parse("<14>1 - - haproxy - - - \u{feff}Hello".as_bytes());
+        assert_eq!((message.timestamp, message.hostname, message.pid, message.msgid), (None, None, None, None));
+        assert_eq!((message.app, message.structured_data), (some("haproxy"), None));
+        assert_eq!(message.text, "Hello");
+
+        // Structured data without message text
+        let message = Message::parse(br#"<14>1 - - - - - [a@1 x="]"]"#);
+        assert_eq!(message.structured_data, some(r#"[a@1 x="]"]"#));
+        assert_eq!(message.text, "");
+    }
+
+    #[test]
+    fn socket_settings() {
+        assert_eq!("0660".parse(), Ok(SocketMode(0o660)));
+        assert!("0800".parse::<SocketMode>().is_err());
+        assert!("1777".parse::<SocketMode>().is_err());
+        assert_eq!("99".parse(), Ok(SocketOwner { uid: Some(99), gid: None }));
+        assert_eq!("99:100".parse(), Ok(SocketOwner { uid: Some(99), gid: Some(100) }));
+        assert_eq!(":100".parse(), Ok(SocketOwner { uid: None, gid: Some(100) }));
+        assert!(":".parse::<SocketOwner>().is_err());
+        assert!("haproxy".parse::<SocketOwner>().is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::log;
+use std::{
+    collections::BTreeMap,
+    fmt::{ self, Display, Formatter }
+};
+
+
+/// The upper bounds of the latency histogram buckets in milliseconds
+pub const LATENCY_BUCKETS_MS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+/// The maximum amount of tracked frontend/backend/server combinations
+const MAX_SERIES: usize = 4096;
+
+
+/// The key of a traffic series
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Series {
+    /// The frontend name (without the `~` SSL marker)
+    pub frontend: String,
+    /// The backend name
+    pub backend: String,
+    /// The server name
+    pub server: String
+}
+impl Display for Series {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} -> {}/{}", self.frontend, self.backend, self.server)
+    }
+}
+
+
+/// A request parsed from a HAProxy HTTP log line in the default format (`option httplog`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The frontend, backend and server
+    pub series: Series,
+    /// The HTTP status code (or `None` if no response has been received)
+    pub status: Option<u16>,
+    /// The amount of bytes sent to the client
+    pub bytes: u64,
+    /// The total time in milliseconds (or `None` if the request has been aborted)
+    pub total_ms: Option<u64>
+}
+impl Request {
+    /// Parses a log line like `1.2.3.4:5678 [18/Oct/2026:20:50:44.123] fe be/srv 0/0/1/2/3 200 512 ...`; returns `None`
+    /// if the line is not an HTTP log line
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_ascii_whitespace();
+        let (_client, date) = (words.next()?, words.next()?);
+        if !date.starts_with('[') || !date.ends_with(']') {
+            return None;
+        }
+
+        // Parse the names
+        let frontend = words.next()?.trim_end_matches('~');
+        let (backend, server) = words.next()?.split_once('/')?;
+
+        // Parse the timers (`TR/Tw/Tc/Tr/Ta`; TCP logs only have three timers)
+        let timers: Vec<_> = words.next()?.split('/').collect();
+        let [_, _, _, _, total] = timers.as_slice() else {
+            return None;
+        };
+        let total_ms = total.trim_start_matches('+').parse::<i64>().ok()?;
+
+        // Parse the status and the size
+        let status = words.next()?.parse::<i32>().ok()?;
+        let bytes = words.next()?.trim_start_matches('+').parse().ok()?;
+        let series = Series { frontend: frontend.to_string(), backend: backend.to_string(), server: server.to_string() };
+        Some(Self { series, status: u16::try_from(status).ok(), bytes, total_ms: u64::try_from(total_ms).ok() })
+    }
+}
+
+
+/// The class of an HTTP status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StatusClass {
+    /// `1xx`
+    Informational,
+    /// `2xx`
+    Success,
+    /// `3xx`
+    Redirection,
+    /// `4xx`
+    ClientError,
+    /// `5xx`
+    ServerError,
+    /// No or an invalid status code (e.g. aborted requests)
+    Other
+}
+impl StatusClass {
+    /// All status classes
+    pub const ALL: [Self; 6] = [
+        Self::Informational, Self::Success, Self::Redirection, Self::ClientError, Self::ServerError, Self::Other
+    ];
+
+    /// The name of the log field that counts the status class
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            Self::Informational => "status_1xx",
+            Self::Success => "status_2xx",
+            Self::Redirection => "status_3xx",
+            Self::ClientError => "status_4xx",
+            Self::ServerError => "status_5xx",
+            Self::Other => "status_other"
+        }
+    }
+
+    /// Gets the class of `status`
+    pub fn of(status: Option<u16>) -> Self {
+        match status {
+            Some(100..=199) => Self::Informational,
+            Some(200..=299) => Self::Success,
+            Some(300..=399) => Self::Redirection,
+            Some(400..=499) => Self::ClientError,
+            Some(500..=599) => Self::ServerError,
+            _ => Self::Other
+        }
+    }
+}
+impl Display for StatusClass {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Informational => write!(f, "1xx"),
+            Self::Success => write!(f, "2xx"),
+            Self::Redirection => write!(f, "3xx"),
+            Self::ClientError => write!(f, "4xx"),
+            Self::ServerError => write!(f, "5xx"),
+            Self::Other => write!(f, "other")
+        }
+    }
+}
+
+
+/// The traffic counters of a frontend/backend/server combination
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// The amount of requests
+    pub requests: u64,
+    /// The amount of requests per status class (indexed by [`StatusClass`])
+    pub statuses: [u64; 6],
+    /// The amount of bytes sent to the clients
+    pub bytes: u64,
+    /// The amount of completed requests per latency bucket (indexed like [`LATENCY_BUCKETS_MS`] plus `+Inf`)
+    pub latency_buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    /// The sum of the latencies of all completed requests in milliseconds
+    pub latency_sum_ms: u64
+}
+impl Counters {
+    /// The amount of requests with the status `class`
+    pub fn status(&self, class: StatusClass) -> u64 {
+        self.statuses[class as usize]
+    }
+    /// The amount of completed requests
+    pub fn completed(&self) -> u64 {
+        self.latency_buckets.iter().sum()
+    }
+    /// Estimates the latency quantile `q` (`0.0..=1.0`) as upper bound of the bucket that contains it (or `None` if
+    /// there are no completed requests or the quantile lies beyond the last bucket)
+    pub fn latency_quantile_ms(&self, q: f64) -> Option<u64> {
+        let rank = (self.completed() as f64 * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        let bucket = self.latency_buckets.iter().position(|count| {
+            seen += count;
+            seen >= rank
+        });
+        LATENCY_BUCKETS_MS.get(bucket?).copied()
+    }
+
+    /// Records `request`
+    fn record(&mut self, request: &Request) {
+        self.requests += 1;
+        self.statuses[StatusClass::of(request.status) as usize] += 1;
+        self.bytes += request.bytes;
+        if let Some(total_ms) = request.total_ms {
+            let bucket = LATENCY_BUCKETS_MS.iter().position(|bound| total_ms <= *bound).unwrap_or(LATENCY_BUCKETS_MS.len());
+            self.latency_buckets[bucket] += 1;
+            self.latency_sum_ms += total_ms;
+        }
+    }
+}
+
+
+/// Aggregates traffic counters per frontend, backend and server from HAProxy's HTTP log lines
+#[derive(Debug, Clone, Default)]
+pub struct Traffic {
+    /// The counters per series
+    series: BTreeMap<Series, Counters>,
+    /// The amount of requests that have been dropped because there were too many series
+    dropped: u64
+}
+impl Traffic {
+    /// Creates a new empty aggregation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the request within the log line `line` (if it is an HTTP log line)
+    pub fn record(&mut self, line: &str) {
+        let Some(request) = Request::parse(line) else {
+            return;
+        };
+        let known = self.series.contains_key(&request.series);
+        match known || self.series.len() < MAX_SERIES {
+            true => self.series.entry(request.series.clone()).or_default().record(&request),
+            false => self.dropped += 1
+        }
+    }
+
+    /// The counters per series
+    pub fn series(&self) -> impl Iterator<Item = (&Series, &Counters)> {
+        self.series.iter()
+    }
+    /// The amount of requests that have been dropped because there were too many series
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Logs the counters of each series
+    pub fn log_summary(&self) {
+        for (series, counters) in &self.series {
+            // Format the message
+            let quantile = |q| counters.latency_quantile_ms(q);
+            let format_ms = |ms: Option<u64>| ms.map_or_else(|| "-".to_string(), |ms| format!("<={}ms", ms));
+            let message = format!(
+                "Traffic {}: {} requests ({} 2xx, {} 4xx, {} 5xx), {} bytes, p50 {}, p99 {}", series, counters.requests,
+                counters.status(StatusClass::Success), counters.status(StatusClass::ClientError),
+                counters.status(StatusClass::ServerError), counters.bytes, format_ms(quantile(0.5)), format_ms(quantile(0.99))
+            );
+
+            // Add the fields
+            let mut record = log::info("traffic", message).field("frontend", series.frontend.as_str())
+                .field("backend", series.backend.as_str()).field("server", series.server.as_str())
+                .field("requests", counters.requests).field("bytes", counters.bytes);
+            for class in StatusClass::ALL {
+                record = record.field(class.field_name(), counters.status(class));
+            }
+            for (name, q) in [("latency_p50_ms", 0.5), ("latency_p95_ms", 0.95), ("latency_p99_ms", 0.99)] {
+                if let Some(ms) = quantile(q) {
+                    record = record.field(name, ms);
+                }
+            }
+            record.emit();
+        }
+        if self.dropped > 0 {
+            let message = format!("Dropped {} requests because there are too many backends and servers", self.dropped);
+            log::warn("traffic", message).field("dropped", self.dropped).emit();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a series
+    fn series(frontend: &str, backend: &str, server: &str) -> Series {
+        Series { frontend: frontend.to_string(), backend: backend.to_string(), server: server.to_string() }
+    }
+
+    #[test]
+    fn completed_request() {
+        let line = "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe~ be/srv1 0/0/1/2/30 200 512 - - ---- 1/1/0/0/0 0/0 \
+            \"GET / HTTP/1.1\"";
+        let request = Request::parse(line).expect("not an HTTP log line");
+        let expected = Request { series: series("fe", "be", "srv1"), status: Some(200), bytes: 512, total_ms: Some(30) };
+        assert_eq!(request, expected);
+    }
+
+    #[test]
+    fn aborted_request() {
+        // The client aborted before the response: the total time and the status are `-1`
+        let line = "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe be/srv1 0/0/1/-1/-1 -1 0 CH-- \"GET / HTTP/1.1\"";
+        let request = Request::parse(line).expect("not an HTTP log line");
+        assert_eq!((request.status, request.bytes, request.total_ms), (None, 0, None));
+
+        // The request never reached a server but has a total time
+        let line = "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe be/<NOSRV> -1/-1/-1/-1/+5 503 +217 SC-- \"GET / HTTP/1.1\"";
+        let request = Request::parse(line).expect("not an HTTP log line");
+        assert_eq!(request.series, series("fe", "be", "<NOSRV>"));
+        assert_eq!((request.status, request.bytes, request.total_ms), (Some(503), 217, Some(5)));
+    }
+
+    #[test]
+    fn other_lines() {
+        for line in [
+            "Proxy fe started.",
+            "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe be/srv1 0/0/5 512 --",
+            "10.0.0.1:5678 18/Oct/2026 fe be/srv1 0/0/1/2/30 200 512",
+            "10.0.0.1:5678 [18/Oct/2026:20:50:44.123] fe be 0/0/1/2/30 200 512"
+        ] {
+            assert_eq!(Request::parse(line), None, "{:?} should not be parsed", line);
+        }
+    }
+
+    #[test]
+    fn aborted_requests_are_counted_without_latency() {
+        let mut traffic = Traffic::new();
+        traffic.record("10.0.0.1:1 [18/Oct/2026:20:50:44.123] fe be/srv 0/0/1/2/30 200 100 -- \"GET / HTTP/1.1\"");
+        traffic.record("10.0.0.1:2 [18/Oct/2026:20:50:44.123] fe be/srv 0/0/1/-1/-1 -1 0 CH-- \"GET / HTTP/1.1\"");
+        traffic.record("Proxy fe started.");
+        let (key, counters) = traffic.series().next().expect("no series");
+        assert_eq!(*key, series("fe", "be", "srv"));
+        assert_eq!((counters.requests, counters.bytes, counters.completed()), (2, 100, 1));
+        assert_eq!((counters.status(StatusClass::Success), counters.status(StatusClass::Other)), (1, 1));
+        assert_eq!((counters.latency_sum_ms, counters.latency_quantile_ms(0.5)), (30, Some(50)));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::log;
+use std::{
+    fs,
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    str::FromStr
+};
+
+
+/// The marker file that commits all pending changes in [`TransactionMode::Commit`]
+pub const COMMIT_MARKER: &str = ".commit";
+/// The marker file that holds back all changes while it exists in [`TransactionMode::Lock`]
+pub const LOCK_MARKER: &str = ".lock";
+
+
+/// An identifier for a specific instance of a marker file (device, inode, modification time)
+pub type MarkerId = (u64, u64, i64, i64);
+
+
+/// Removes the marker `name` within `directory` if it exists
+pub fn consume_marker<D>(directory: D, name: &str) where D: AsRef<Path> {
+    match fs::remove_file(directory.as_ref().join(name)) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => log::warn("marker", format!("Failed to consume {}: {}", name, e)).field("marker", name).error(e).emit()
+    }
+}
+
+
+/// The transaction mode of an inbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionMode {
+    /// Changes are applied as they occur
+    Off,
+    /// Changes are only applied once a commit marker appears; the marker is consumed afterwards
+    Commit,
+    /// Changes are held back as long as a lock marker exists
+    Lock
+}
+impl TransactionMode {
+    /// Gets the identifier of the marker `name` within `directory` if it exists
+    pub fn marker<D>(directory: D, name: &str) -> Option<MarkerId> where D: AsRef<Path> {
+        let metadata = fs::symlink_metadata(directory.as_ref().join(name)).ok()?;
+        Some((metadata.dev(), metadata.ino(), metadata.mtime(), metadata.mtime_nsec()))
+    }
+
+    /// Checks whether the transaction within `directory` is complete and returns the commit marker to consume once the
+    /// transaction has been applied (if any)
+    pub fn check<D>(&self, directory: D) -> Result<Option<MarkerId>, String> where D: AsRef<Path> {
+        let directory = directory.as_ref();
+        match self {
+            Self::Off => Ok(None),
+            Self::Lock if Self::marker(directory, LOCK_MARKER).is_some() => Err(format!("{} exists", LOCK_MARKER)),
+            Self::Lock => Ok(None),
+            Self::Commit => match Self::marker(directory, COMMIT_MARKER) {
+                Some(commit) => Ok(Some(commit)),
+                None => Err(format!("no {} marker", COMMIT_MARKER))
+            }
+        }
+    }
+
+    /// Consumes the commit marker `commit` within `directory` after its transaction has been applied
+    ///
+    /// # Note
+    /// The marker is kept if it has been replaced in the meantime, since it then belongs to the next transaction.
+    pub fn consume<D>(&self, directory: D, commit: Option<MarkerId>) where D: AsRef<Path> {
+        let directory = directory.as_ref();
+        if commit.is_some() && Self::marker(directory, COMMIT_MARKER) == commit {
+            consume_marker(directory, COMMIT_MARKER);
+        }
+    }
+}
+impl FromStr for TransactionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "commit" => Ok(Self::Commit),
+            "lock" => Ok(Self::Lock),
+            s => Err(format!("Invalid transaction mode: {} (expected off, commit or lock)", s))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsext;
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!("off".parse(), Ok(TransactionMode::Off));
+        assert_eq!("commit".parse(), Ok(TransactionMode::Commit));
+        assert_eq!("lock".parse(), Ok(TransactionMode::Lock));
+        assert!("Commit".parse::<TransactionMode>().is_err());
+    }
+
+    #[test]
+    fn check_off_and_lock() {
+        let directory = fsext::test_dir("transaction-lock");
+        assert_eq!(TransactionMode::Off.check(&directory), Ok(None));
+        assert_eq!(TransactionMode::Lock.check(&directory), Ok(None));
+
+        // A lock marker holds back changes in lock mode only
+        fs::write(directory.join(LOCK_MARKER), "").expect("failed to create marker");
+        assert_eq!(TransactionMode::Off.check(&directory), Ok(None));
+        assert_eq!(TransactionMode::Lock.check(&directory), Err(".lock exists".to_string()));
+    }
+
+    #[test]
+    fn check_and_consume_commit() {
+        let directory = fsext::test_dir("transaction-commit");
+        assert_eq!(TransactionMode::Commit.check(&directory), Err("no .commit marker".to_string()));
+
+        // A complete transaction returns its commit marker, which is consumed after it has been applied
+        fs::write(directory.join(COMMIT_MARKER), "").expect("failed to create marker");
+        let commit = TransactionMode::Commit.check(&directory).expect("transaction is not complete");
+        assert!(commit.is_some());
+        assert_eq!(commit, TransactionMode::marker(&directory, COMMIT_MARKER));
+        TransactionMode::Commit.consume(&directory, commit);
+        assert_eq!(TransactionMode::marker(&directory, COMMIT_MARKER), None);
+    }
+
+    #[test]
+    fn replaced_commit_markers_are_kept() {
+        let directory = fsext::test_dir("transaction-replaced");
+        fs::write(directory.join(COMMIT_MARKER), "").expect("failed to create marker");
+        let commit = TransactionMode::Commit.check(&directory).expect("transaction is not complete");
+
+        // The next transaction replaces the marker while the previous one is applied
+        fs::write(directory.join("next"), "").expect("failed to create marker");
+        fs::rename(directory.join("next"), directory.join(COMMIT_MARKER)).expect("failed to replace marker");
+        TransactionMode::Commit.consume(&directory, commit);
+        assert!(TransactionMode::marker(&directory, COMMIT_MARKER).is_some());
+
+        // Consuming without a commit keeps the marker as well
+        TransactionMode::Commit.consume(&directory, None);
+        assert!(TransactionMode::marker(&directory, COMMIT_MARKER).is_some());
+    }
+}